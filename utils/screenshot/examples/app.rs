@@ -1,10 +1,11 @@
 use nannou::prelude::*;
-use screenshot::Shots;
+use screenshot::{Interval, Shots};
 use std::time::Duration;
 
 struct Model {
     screenshot: Shots,
     subdir_count: usize,
+    replay: bool,
 }
 
 fn main() {
@@ -19,10 +20,14 @@ fn model(app: &App) -> Model {
         .event(window_event)
         .build()
         .unwrap();
-    let screenshot = Shots::new(app, window_id, env!("CARGO_MANIFEST_DIR"));
+    let screenshot = Shots::new(app, window_id, env!("CARGO_MANIFEST_DIR"))
+        .expect("Failed to set up screenshots");
+    // Sinks that write one file per frame can also be
+    // spread over more threads with `encoding_threads`
     Model {
         screenshot,
         subdir_count: 0,
+        replay: false,
     }
 }
 
@@ -69,7 +74,12 @@ fn view(app: &App, model: &Model, frame: &Frame) {
     draw.to_frame(app, &frame).unwrap();
 
     // This only captures if take() is called
-    model.screenshot.capture(&frame);
+    if let Err(e) = model.screenshot.capture(&frame) {
+        eprintln!("screenshot: {}", e);
+    }
+    for e in model.screenshot.errors() {
+        eprintln!("screenshot: {}", e);
+    }
 }
 
 fn window_event(_app: &App, model: &mut Model, event: WindowEvent) {
@@ -79,10 +89,43 @@ fn window_event(_app: &App, model: &mut Model, event: WindowEvent) {
                 Key::S => {
                     model.screenshot.take();
                 }
+                Key::R => {
+                    if model.screenshot.is_recording() {
+                        model.screenshot.stop_recording();
+                    } else if let Err(e) = model.screenshot.start_recording() {
+                        eprintln!("screenshot: {}", e);
+                    }
+                }
+                Key::T => {
+                    // A shot every second, at most an hour's worth
+                    let interval = Interval::Every(Duration::from_secs(1));
+                    if let Err(e) = model.screenshot.toggle_timelapse(interval, Some(3600)) {
+                        eprintln!("screenshot: {}", e);
+                    }
+                }
+                Key::P => {
+                    // Keeps the last 10 seconds around for Key::I,
+                    // capturing every frame while on
+                    model.replay = !model.replay;
+                    let length = if model.replay {
+                        Some(Duration::from_secs(10))
+                    } else {
+                        None
+                    };
+                    if let Err(e) = model.screenshot.instant_replay(length) {
+                        eprintln!("screenshot: {}", e);
+                    }
+                }
+                Key::I => {
+                    if let Err(e) = model.screenshot.save_replay() {
+                        eprintln!("screenshot: {}", e);
+                    }
+                }
                 Key::D => {
-                    model
-                        .screenshot
-                        .output_dir(&format!("subdir{}", model.subdir_count));
+                    let subdir = format!("subdir{}", model.subdir_count);
+                    if let Err(e) = model.screenshot.output_dir(&subdir) {
+                        eprintln!("screenshot: {}", e);
+                    }
                     model.subdir_count += 1;
                 }
                 _ => {}
@@ -109,7 +152,12 @@ fn window_event(_app: &App, model: &mut Model, event: WindowEvent) {
 }
 
 fn exit(_: &App, model: Model) {
-    // If you are getting an Access error then you
-    // might need to raise the wait time
-    model.screenshot.flush(Duration::from_secs(3));
+    // Waits for pending screenshots, giving up after the timeout
+    let report = model.screenshot.flush(Duration::from_secs(3));
+    if report.lost + report.failed > 0 {
+        eprintln!(
+            "{} screenshots could not be written",
+            report.lost + report.failed
+        );
+    }
 }
@@ -0,0 +1,150 @@
+use super::format::SampleFormat;
+use std::borrow::Cow;
+
+/// What happens to the alpha channel before a frame is encoded.
+///
+/// Frames are treated as premultiplied, which is what blending
+/// onto a transparent clear colour produces. Modes are applied to
+/// linear samples, before the transfer function.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    /// Save alpha as rendered.
+    Keep,
+    /// Divide colour by alpha, for formats such as PNG that expect straight alpha.
+    Straight,
+    /// Set alpha to 1 and keep the colour.
+    Opaque,
+    /// Composite the frame over an RGB background colour in 0..1, then set alpha to 1.
+    Flatten([f32; 3]),
+}
+
+impl Default for AlphaMode {
+    fn default() -> Self {
+        AlphaMode::Keep
+    }
+}
+
+impl AlphaMode {
+    /// Applies the mode to RGBA `data` of the given sample format.
+    pub(crate) fn apply<'a>(self, data: &'a [u8], sample: SampleFormat) -> Cow<'a, [u8]> {
+        if self == AlphaMode::Keep {
+            return Cow::Borrowed(data);
+        }
+        let size = sample.bytes_per_sample();
+        let mut out = data.to_vec();
+        for pixel in out.chunks_exact_mut(size * 4) {
+            let mut rgba = [0.0; 4];
+            for (c, bytes) in pixel.chunks_exact(size).enumerate() {
+                rgba[c] = read_sample(bytes, sample);
+            }
+            let [r, g, b, a] = rgba;
+            let rgba = match self {
+                AlphaMode::Keep => rgba,
+                AlphaMode::Straight if a > 0.0 => [r / a, g / a, b / a, a],
+                AlphaMode::Straight => [0.0; 4],
+                AlphaMode::Opaque => [r, g, b, 1.0],
+                AlphaMode::Flatten([br, bg, bb]) => {
+                    let t = 1.0 - a.max(0.0).min(1.0);
+                    [r + br * t, g + bg * t, b + bb * t, 1.0]
+                }
+            };
+            for (c, bytes) in pixel.chunks_exact_mut(size).enumerate() {
+                write_sample(bytes, sample, rgba[c]);
+            }
+        }
+        Cow::Owned(out)
+    }
+}
+
+fn read_sample(b: &[u8], sample: SampleFormat) -> f32 {
+    match sample {
+        SampleFormat::U8 => b[0] as f32 / 255.0,
+        SampleFormat::U16 => u16::from_ne_bytes([b[0], b[1]]) as f32 / 65535.0,
+        SampleFormat::F32 => f32::from_ne_bytes([b[0], b[1], b[2], b[3]]),
+    }
+}
+
+fn write_sample(b: &mut [u8], sample: SampleFormat, v: f32) {
+    match sample {
+        SampleFormat::U8 => b[0] = (v.max(0.0).min(1.0) * 255.0).round() as u8,
+        SampleFormat::U16 => {
+            let s = (v.max(0.0).min(1.0) * 65535.0).round() as u16;
+            b.copy_from_slice(&s.to_ne_bytes());
+        }
+        SampleFormat::F32 => b.copy_from_slice(&v.to_ne_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn f32_bytes(samples: &[f32]) -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|s| s.to_ne_bytes().to_vec())
+            .collect()
+    }
+
+    fn apply_f32(mode: AlphaMode, samples: &[f32]) -> Vec<f32> {
+        mode.apply(&f32_bytes(samples), SampleFormat::F32)
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    }
+
+    const HALF: [f32; 4] = [0.25, 0.5, 0.1, 0.5];
+    const CLEAR: [f32; 4] = [0.0, 0.0, 0.0, 0.0];
+
+    #[test]
+    fn keep_borrows() {
+        let data = f32_bytes(&HALF);
+        match AlphaMode::Keep.apply(&data, SampleFormat::F32) {
+            Cow::Borrowed(kept) => assert_eq!(kept, &data[..]),
+            Cow::Owned(_) => panic!("frame was copied"),
+        }
+    }
+
+    #[test]
+    fn straight_divides_by_alpha() {
+        assert_eq!(apply_f32(AlphaMode::Straight, &HALF), [0.5, 1.0, 0.2, 0.5]);
+        assert_eq!(apply_f32(AlphaMode::Straight, &CLEAR), CLEAR);
+        // Colour left over where nothing was drawn is dropped
+        let stray = [0.1, 0.2, 0.3, 0.0];
+        assert_eq!(apply_f32(AlphaMode::Straight, &stray), CLEAR);
+    }
+
+    #[test]
+    fn opaque_keeps_colour() {
+        assert_eq!(apply_f32(AlphaMode::Opaque, &HALF), [0.25, 0.5, 0.1, 1.0]);
+        assert_eq!(apply_f32(AlphaMode::Opaque, &CLEAR), [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn flatten_composites_over_the_background() {
+        let white = AlphaMode::Flatten([1.0, 1.0, 1.0]);
+        assert_eq!(apply_f32(white, &HALF), [0.75, 1.0, 0.6, 1.0]);
+        let background = AlphaMode::Flatten([0.2, 0.4, 0.6]);
+        assert_eq!(apply_f32(background, &CLEAR), [0.2, 0.4, 0.6, 1.0]);
+        let opaque = [0.3, 0.2, 0.1, 1.0];
+        assert_eq!(apply_f32(background, &opaque), opaque);
+    }
+
+    #[test]
+    fn integer_samples() {
+        let straight = AlphaMode::Straight.apply(&[64, 128, 26, 128], SampleFormat::U8);
+        assert_eq!(&*straight, &[128, 255, 52, 128]);
+        let clear = AlphaMode::Flatten([1.0, 1.0, 1.0]).apply(&[0; 4], SampleFormat::U8);
+        assert_eq!(&*clear, &[255; 4]);
+        let half: Vec<u8> = [16384u16, 0, 0, 32768]
+            .iter()
+            .flat_map(|s| s.to_ne_bytes().to_vec())
+            .collect();
+        let opaque = AlphaMode::Opaque.apply(&half, SampleFormat::U16);
+        let opaque: Vec<u16> = opaque
+            .chunks_exact(2)
+            .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(opaque, [16384, 0, 0, 65535]);
+    }
+}
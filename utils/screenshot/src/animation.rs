@@ -0,0 +1,721 @@
+use super::format;
+use super::sink::{Counter, FrameInfo, FrameSink};
+use color_quant::NeuQuant;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// How many times an animation plays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Loop {
+    Forever,
+    /// Total number of plays, at least one.
+    Times(u16),
+}
+
+/// How colours between the entries of a GIF palette are approximated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dither {
+    /// Nearest palette colour. Gradients show banding.
+    None,
+    /// Error diffusion. Smoothest, but the noise changes from frame to frame.
+    FloydSteinberg,
+    /// 4x4 Bayer pattern, which stays put between frames.
+    Ordered,
+}
+
+/// Encodes the frames between two flushes, such as one recording,
+/// into a single animated GIF named after the first frame.
+///
+/// Frames are quantised and streamed to disk as they arrive.
+/// Alpha is dropped.
+///
+/// ```no_run
+/// # use screenshot::{Dither, GifSink, ScreenshotError, Shots};
+/// # use std::time::Duration;
+/// # fn record(screenshot: &Shots) -> Result<(), ScreenshotError> {
+/// screenshot.set_sink(GifSink::new().delay(Duration::from_millis(40)).dither(Dither::Ordered))?;
+/// screenshot.record_frames(100)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct GifSink {
+    delay: Duration,
+    loops: Loop,
+    colors: usize,
+    speed: i32,
+    dither: Dither,
+    shared_palette: bool,
+    stream: Option<GifStream>,
+}
+
+struct GifStream {
+    encoder: gif::Encoder<SharedWriter>,
+    file: SharedWriter,
+    dims: (usize, usize),
+    palette: Option<NeuQuant>,
+    frames: u32,
+}
+
+/// Encodes the frames between two flushes into a single
+/// animated PNG named after the first frame, keeping alpha.
+///
+/// ```no_run
+/// # use screenshot::{ApngSink, Loop, ScreenshotError, Shots};
+/// # fn record(screenshot: &Shots) -> Result<(), ScreenshotError> {
+/// screenshot.set_sink(ApngSink::new().loops(Loop::Times(1)))?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ApngSink {
+    delay: Duration,
+    loops: Loop,
+    stream: Option<ApngStream>,
+}
+
+struct ApngStream {
+    file: Counter<BufWriter<File>>,
+    dims: (usize, usize),
+    frames: u32,
+    sequence: u32,
+    actl_offset: u64,
+}
+
+/// Lets the file be flushed after the GIF encoder writing to it is dropped.
+#[derive(Clone)]
+struct SharedWriter(Arc<Mutex<Counter<BufWriter<File>>>>);
+
+impl GifSink {
+    /// 30 frames per second, looping forever, with a 256 colour
+    /// palette per frame and Floyd-Steinberg dithering.
+    pub fn new() -> Self {
+        GifSink {
+            delay: Duration::from_secs(1) / 30,
+            loops: Loop::Forever,
+            colors: 256,
+            speed: 10,
+            dither: Dither::FloydSteinberg,
+            shared_palette: false,
+            stream: None,
+        }
+    }
+
+    /// Time each frame is shown. GIF counts in hundredths of a second,
+    /// so rounding is spread over the frames to keep the overall rate.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn loops(mut self, loops: Loop) -> Self {
+        self.loops = loops;
+        self
+    }
+
+    /// Palette size from 2 to 256.
+    pub fn colors(mut self, colors: usize) -> Self {
+        self.colors = colors.max(2).min(256);
+        self
+    }
+
+    /// Quantiser speed from 1 (best palette) to 30 (fastest), 10 by default.
+    pub fn speed(mut self, speed: i32) -> Self {
+        self.speed = speed.max(1).min(30);
+        self
+    }
+
+    pub fn dither(mut self, dither: Dither) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// Quantises the first frame only and uses its palette for the whole
+    /// animation. This avoids colour flicker and makes smaller files,
+    /// but colours that first appear in later frames are approximated.
+    pub fn shared_palette(mut self, shared: bool) -> Self {
+        self.shared_palette = shared;
+        self
+    }
+
+    fn start(&self, info: &FrameInfo, rgba: &[u8]) -> io::Result<GifStream> {
+        let (w, h) = gif_dims(info.dims)?;
+        let file = SharedWriter(Arc::new(Mutex::new(Counter::new(BufWriter::new(
+            File::create(&info.path)?,
+        )))));
+        let palette = if self.shared_palette {
+            Some(NeuQuant::new(self.speed, self.colors, rgba))
+        } else {
+            None
+        };
+        let global = palette
+            .as_ref()
+            .map(|quant| quant.color_map_rgb())
+            .unwrap_or_default();
+        let mut encoder = gif::Encoder::new(file.clone(), w, h, &global)?;
+        let repeat = match self.loops {
+            Loop::Forever => gif::Repeat::Infinite,
+            // A count of zero leaves the extension out, which plays once
+            Loop::Times(n) => gif::Repeat::Finite(n.saturating_sub(1)),
+        };
+        encoder.write_extension(gif::ExtensionData::Repetitions(repeat))?;
+        Ok(GifStream {
+            encoder,
+            file,
+            dims: info.dims,
+            palette,
+            frames: 0,
+        })
+    }
+}
+
+impl Default for GifSink {
+    fn default() -> Self {
+        GifSink::new()
+    }
+}
+
+impl FrameSink for GifSink {
+    fn write(&mut self, info: &FrameInfo, data: &[u8]) -> io::Result<u64> {
+        let mut rgba = format::to_u8(data, info.sample).into_owned();
+        for pixel in rgba.chunks_exact_mut(4) {
+            pixel[3] = 255;
+        }
+        if self.stream.is_none() {
+            self.stream = Some(self.start(info, &rgba)?);
+        }
+        let (delay, speed, colors, dither) = (self.delay, self.speed, self.colors, self.dither);
+        let stream = self.stream.as_mut().unwrap();
+        check_dims(stream.dims, info.dims)?;
+        let (w, h) = gif_dims(info.dims)?;
+        let local;
+        let quant = match &stream.palette {
+            Some(quant) => quant,
+            None => {
+                local = NeuQuant::new(speed, colors, &rgba);
+                &local
+            }
+        };
+        let indices = quantize(quant, &rgba, info.dims.0, colors, dither);
+        let mut frame = match stream.palette {
+            Some(_) => gif::Frame::from_indexed_pixels(w, h, &indices, None),
+            None => gif::Frame::from_palette_pixels(w, h, &indices, &quant.color_map_rgb(), None),
+        };
+        frame.delay = frame_delay(delay, stream.frames, 100);
+        stream.encoder.write_frame(&frame)?;
+        stream.frames += 1;
+        Ok(stream.file.lock()?.take())
+    }
+
+    fn extension(&self) -> &str {
+        "gif"
+    }
+
+    fn flush(&mut self) -> io::Result<u64> {
+        match self.stream.take() {
+            Some(GifStream { encoder, file, .. }) => {
+                // Dropping the encoder writes the trailer
+                drop(encoder);
+                let mut file = file.lock()?;
+                file.flush()?;
+                Ok(file.take())
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+impl ApngSink {
+    /// 30 frames per second, looping forever.
+    pub fn new() -> Self {
+        ApngSink {
+            delay: Duration::from_secs(1) / 30,
+            loops: Loop::Forever,
+            stream: None,
+        }
+    }
+
+    /// Time each frame is shown, rounded to milliseconds.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn loops(mut self, loops: Loop) -> Self {
+        self.loops = loops;
+        self
+    }
+
+    fn start(&self, info: &FrameInfo) -> io::Result<ApngStream> {
+        let (w, h) = (info.dims.0 as u32, info.dims.1 as u32);
+        let mut file = Counter::new(BufWriter::new(File::create(&info.path)?));
+        file.write_all(b"\x89PNG\r\n\x1a\n")?;
+        let mut ihdr = vec![];
+        ihdr.extend_from_slice(&w.to_be_bytes());
+        ihdr.extend_from_slice(&h.to_be_bytes());
+        // 8 bit RGBA, deflate, adaptive filtering, not interlaced
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+        write_chunk(&mut file, b"IHDR", &ihdr)?;
+        let actl_offset = file.seek(SeekFrom::Current(0))?;
+        // The frame count is filled in once the animation is complete
+        write_chunk(&mut file, b"acTL", &self.actl(0))?;
+        Ok(ApngStream {
+            file,
+            dims: info.dims,
+            frames: 0,
+            sequence: 0,
+            actl_offset,
+        })
+    }
+
+    fn actl(&self, frames: u32) -> Vec<u8> {
+        let plays = match self.loops {
+            Loop::Forever => 0,
+            Loop::Times(n) => n.max(1) as u32,
+        };
+        let mut actl = frames.to_be_bytes().to_vec();
+        actl.extend_from_slice(&plays.to_be_bytes());
+        actl
+    }
+}
+
+impl Default for ApngSink {
+    fn default() -> Self {
+        ApngSink::new()
+    }
+}
+
+impl FrameSink for ApngSink {
+    fn write(&mut self, info: &FrameInfo, data: &[u8]) -> io::Result<u64> {
+        if self.stream.is_none() {
+            self.stream = Some(self.start(info)?);
+        }
+        let delay = self.delay;
+        let stream = self.stream.as_mut().unwrap();
+        check_dims(stream.dims, info.dims)?;
+        let (w, h) = (info.dims.0 as u32, info.dims.1 as u32);
+        let mut fctl = stream.sequence.to_be_bytes().to_vec();
+        for &v in [w, h, 0, 0].iter() {
+            fctl.extend_from_slice(&v.to_be_bytes());
+        }
+        fctl.extend_from_slice(&frame_delay(delay, stream.frames, 1000).to_be_bytes());
+        fctl.extend_from_slice(&1000u16.to_be_bytes());
+        // No disposal, replace the previous frame
+        fctl.extend_from_slice(&[0, 0]);
+        write_chunk(&mut stream.file, b"fcTL", &fctl)?;
+        stream.sequence += 1;
+
+        let rgba = format::to_u8(data, info.sample);
+        let compressed = deflate::deflate_bytes_zlib(&filter_rows(&rgba, info.dims.0 * 4));
+        if stream.frames == 0 {
+            write_chunk(&mut stream.file, b"IDAT", &compressed)?;
+        } else {
+            let mut fdat = stream.sequence.to_be_bytes().to_vec();
+            fdat.extend_from_slice(&compressed);
+            write_chunk(&mut stream.file, b"fdAT", &fdat)?;
+            stream.sequence += 1;
+        }
+        stream.frames += 1;
+        Ok(stream.file.take())
+    }
+
+    fn flush(&mut self) -> io::Result<u64> {
+        match self.stream.take() {
+            Some(mut stream) => {
+                write_chunk(&mut stream.file, b"IEND", &[])?;
+                // The rewritten acTL replaces bytes already counted
+                let bytes = stream.file.take();
+                stream.file.seek(SeekFrom::Start(stream.actl_offset))?;
+                write_chunk(&mut stream.file, b"acTL", &self.actl(stream.frames))?;
+                stream.file.flush()?;
+                Ok(bytes)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn extension(&self) -> &str {
+        "png"
+    }
+}
+
+impl SharedWriter {
+    fn lock(&self) -> io::Result<MutexGuard<'_, Counter<BufWriter<File>>>> {
+        self.0
+            .lock()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "GIF writer poisoned"))
+    }
+}
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock()?.flush()
+    }
+}
+
+fn check_dims(expected: (usize, usize), dims: (usize, usize)) -> io::Result<()> {
+    if dims == expected {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "frame is {}x{} but the animation is {}x{}",
+                dims.0, dims.1, expected.0, expected.1
+            ),
+        ))
+    }
+}
+
+fn gif_dims(dims: (usize, usize)) -> io::Result<(u16, u16)> {
+    let max = std::u16::MAX as usize;
+    if dims.0 > max || dims.1 > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "GIF frames are limited to 65535 pixels per side",
+        ));
+    }
+    Ok((dims.0 as u16, dims.1 as u16))
+}
+
+/// Delay of frame `n` in units of `1 / per_second` seconds.
+/// Rounding is carried over to the following frames so the
+/// animation keeps the requested rate on average.
+fn frame_delay(delay: Duration, n: u32, per_second: u32) -> u16 {
+    let end = |n: u32| {
+        (delay.as_nanos() * (n as u128) * (per_second as u128) + 500_000_000) / 1_000_000_000
+    };
+    (end(n + 1) - end(n)).min(std::u16::MAX as u128) as u16
+}
+
+/// Maps opaque RGBA pixels to indices into the palette of `quant`.
+fn quantize(quant: &NeuQuant, rgba: &[u8], width: usize, colors: usize, dither: Dither) -> Vec<u8> {
+    let index_of = |r: i32, g: i32, b: i32| {
+        let channel = |c: i32| c.max(0).min(255) as u8;
+        quant.index_of(&[channel(r), channel(g), channel(b), 255])
+    };
+    match dither {
+        Dither::None => rgba
+            .chunks_exact(4)
+            .map(|p| quant.index_of(p) as u8)
+            .collect(),
+        Dither::Ordered => {
+            const BAYER: [[i32; 4]; 4] =
+                [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+            // Roughly the distance between neighbouring palette colours
+            let spread = (256.0 / (colors as f32).cbrt()) as i32;
+            rgba.chunks_exact(4)
+                .enumerate()
+                .map(|(i, p)| {
+                    let offset = (BAYER[i / width % 4][i % width % 4] * 2 - 15) * spread / 32;
+                    let c = |c: u8| c as i32 + offset;
+                    index_of(c(p[0]), c(p[1]), c(p[2])) as u8
+                })
+                .collect()
+        }
+        Dither::FloydSteinberg => {
+            let palette = quant.color_map_rgba();
+            // Errors in sixteenths for this row and the next,
+            // padded by a pixel on either side
+            let mut row = vec![[0i32; 3]; width + 2];
+            let mut next = row.clone();
+            let mut indices = Vec::with_capacity(rgba.len() / 4);
+            for line in rgba.chunks_exact(width * 4) {
+                for (x, p) in line.chunks_exact(4).enumerate() {
+                    let mut value = [0; 3];
+                    for c in 0..3 {
+                        value[c] = p[c] as i32 + row[x + 1][c] / 16;
+                    }
+                    let index = index_of(value[0], value[1], value[2]);
+                    indices.push(index as u8);
+                    for c in 0..3 {
+                        let err = value[c].max(0).min(255) - palette[index * 4 + c] as i32;
+                        row[x + 2][c] += err * 7;
+                        next[x][c] += err * 3;
+                        next[x + 1][c] += err * 5;
+                        next[x + 2][c] += err;
+                    }
+                }
+                mem::swap(&mut row, &mut next);
+                for err in next.iter_mut() {
+                    *err = [0; 3];
+                }
+            }
+            indices
+        }
+    }
+}
+
+/// Prefixes every row with the Paeth filter and applies it.
+fn filter_rows(data: &[u8], stride: usize) -> Vec<u8> {
+    const BPP: usize = 4;
+    let mut out = Vec::with_capacity(data.len() + data.len() / stride.max(1));
+    let zeros = vec![0; stride];
+    let mut prev: &[u8] = &zeros;
+    for line in data.chunks_exact(stride) {
+        out.push(4);
+        for i in 0..stride {
+            let a = if i >= BPP { line[i - BPP] } else { 0 };
+            let b = prev[i];
+            let c = if i >= BPP { prev[i - BPP] } else { 0 };
+            out.push(line[i].wrapping_sub(paeth(a, b, c)));
+        }
+        prev = line;
+    }
+    out
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn write_chunk<W: Write>(w: &mut W, name: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(name)?;
+    w.write_all(data)?;
+    w.write_all(&crc32(&[name, data]).to_be_bytes())
+}
+
+/// CRC-32 as used by PNG chunks, computed bitwise.
+pub(crate) fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in parts.iter().flat_map(|part| part.iter()) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::SampleFormat;
+    use crate::metadata::Metadata;
+    use gif::SetParameter;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn info(path: &PathBuf, number: usize, dims: (usize, usize)) -> FrameInfo {
+        FrameInfo {
+            number,
+            dims,
+            sample: SampleFormat::U8,
+            output_dir: path.parent().unwrap().to_path_buf(),
+            path: path.clone(),
+            metadata: Metadata::new(),
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("screenshot-{}-{}", process::id(), name))
+    }
+
+    /// A frame of `dims` filled with one colour.
+    fn solid(dims: (usize, usize), rgba: [u8; 4]) -> Vec<u8> {
+        rgba.iter()
+            .cloned()
+            .cycle()
+            .take(dims.0 * dims.1 * 4)
+            .collect()
+    }
+
+    /// Writes `frames` through `sink`, flushes it and returns
+    /// the file along with the bytes the sink reported.
+    fn encode<S: FrameSink>(
+        mut sink: S,
+        name: &str,
+        dims: (usize, usize),
+        frames: &[Vec<u8>],
+    ) -> (Vec<u8>, u64) {
+        let path = temp_path(name);
+        let mut bytes = 0;
+        for (i, frame) in frames.iter().enumerate() {
+            bytes += sink.write(&info(&path, i + 1, dims), frame).unwrap();
+        }
+        bytes += sink.flush().unwrap();
+        let file = fs::read(&path).unwrap();
+        fs::remove_file(&path).ok();
+        (file, bytes)
+    }
+
+    /// Splits a PNG into its chunks, checking every CRC.
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut chunks = vec![];
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let mut name = [0; 4];
+            name.copy_from_slice(&rest[4..8]);
+            let data = &rest[8..8 + len];
+            let crc = &rest[8 + len..12 + len];
+            assert_eq!(crc, &crc32(&[&name, data]).to_be_bytes()[..]);
+            chunks.push((name, data.to_vec()));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    fn be32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    }
+
+    #[test]
+    fn crc32_known_values() {
+        assert_eq!(crc32(&[b"123456789"]), 0xcbf4_3926);
+        assert_eq!(crc32(&[b"1234", b"", b"56789"]), 0xcbf4_3926);
+        assert_eq!(crc32(&[b"IEND"]), 0xae42_6082);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn frame_delay_carries_rounding() {
+        let delay = Duration::from_secs(1) / 30;
+        let ms: Vec<_> = (0..3).map(|n| frame_delay(delay, n, 1000)).collect();
+        assert_eq!(ms, [33, 34, 33]);
+        let centis: u32 = (0..30).map(|n| frame_delay(delay, n, 100) as u32).sum();
+        assert_eq!(centis, 100);
+        assert_eq!(frame_delay(Duration::from_millis(40), 7, 100), 4);
+        assert_eq!(
+            frame_delay(Duration::from_secs(1000), 0, 1000),
+            std::u16::MAX
+        );
+    }
+
+    #[test]
+    fn apng_chunks_in_order() {
+        let dims = (2, 2);
+        let frames = [
+            solid(dims, [255, 0, 0, 255]),
+            solid(dims, [0, 255, 0, 128]),
+            solid(dims, [0, 0, 255, 0]),
+        ];
+        let sink = ApngSink::new()
+            .delay(Duration::from_millis(50))
+            .loops(Loop::Times(2));
+        let (png, bytes) = encode(sink, "anim.png", dims, &frames);
+        assert_eq!(bytes, png.len() as u64);
+        let chunks = chunks(&png);
+        let names: Vec<_> = chunks.iter().map(|(name, _)| &name[..]).collect();
+        assert_eq!(
+            names,
+            [
+                &b"IHDR"[..],
+                b"acTL",
+                b"fcTL",
+                b"IDAT",
+                b"fcTL",
+                b"fdAT",
+                b"fcTL",
+                b"fdAT",
+                b"IEND"
+            ]
+        );
+        // Rewritten on flush with the final frame count
+        assert_eq!(be32(&chunks[1].1, 0), 3);
+        assert_eq!(be32(&chunks[1].1, 4), 2);
+        // fcTL and fdAT share one sequence
+        let sequence: Vec<_> = chunks[2..8]
+            .iter()
+            .map(|(name, data)| match name {
+                b"IDAT" => None,
+                _ => Some(be32(data, 0)),
+            })
+            .collect();
+        assert_eq!(
+            sequence,
+            [Some(0), None, Some(1), Some(2), Some(3), Some(4)]
+        );
+        let fctl = &chunks[2].1;
+        assert_eq!((be32(fctl, 4), be32(fctl, 8)), (2, 2));
+        assert_eq!(&fctl[20..24], &[0, 50, 0x03, 0xe8]);
+    }
+
+    #[test]
+    fn apng_decodes() {
+        let dims = (3, 2);
+        let first: Vec<u8> = (0..24).map(|i| i * 10).collect();
+        let frames = [first.clone(), solid(dims, [9, 8, 7, 6])];
+        let (png, _) = encode(ApngSink::new(), "decode.png", dims, &frames);
+        let (info, mut reader) = png::Decoder::new(&png[..]).read_info().unwrap();
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(info.color_type, png::ColorType::RGBA);
+        let control = reader.info().animation_control().unwrap();
+        assert_eq!((control.num_frames, control.num_plays), (2, 0));
+        let mut decoded = vec![0; info.buffer_size()];
+        reader.next_frame(&mut decoded).unwrap();
+        assert_eq!(decoded, first);
+    }
+
+    fn decode_gif(gif: &[u8]) -> Vec<(u16, Vec<u8>)> {
+        let mut decoder = gif::Decoder::new(gif);
+        decoder.set(gif::ColorOutput::RGBA);
+        let mut reader = decoder.read_info().unwrap();
+        let mut frames = vec![];
+        while let Some(frame) = reader.read_next_frame().unwrap() {
+            frames.push((frame.delay, frame.buffer.to_vec()));
+        }
+        frames
+    }
+
+    fn close(a: &[u8], b: &[u8], tolerance: u8) -> bool {
+        a.len() == b.len()
+            && a.iter()
+                .zip(b)
+                .all(|(&a, &b)| (a as i16 - b as i16).abs() <= tolerance as i16)
+    }
+
+    #[test]
+    fn gif_decodes_for_every_palette_and_dither() {
+        let dims = (4, 4);
+        let colours = [[200, 30, 40, 255], [20, 180, 60, 100], [10, 20, 220, 255]];
+        let frames: Vec<_> = colours.iter().map(|&c| solid(dims, c)).collect();
+        for &shared in &[false, true] {
+            for &dither in &[Dither::None, Dither::Ordered, Dither::FloydSteinberg] {
+                let sink = GifSink::new()
+                    .delay(Duration::from_millis(40))
+                    .colors(16)
+                    .shared_palette(shared)
+                    .dither(dither);
+                let (gif, bytes) = encode(sink, "anim.gif", dims, &frames);
+                assert_eq!(bytes, gif.len() as u64);
+                assert_eq!(gif.last(), Some(&0x3b));
+                let decoded = decode_gif(&gif);
+                assert_eq!(decoded.len(), 3);
+                // The shared palette only knows the first frame's colours
+                let checked = if shared { 1 } else { 3 };
+                for ((delay, rgba), &colour) in decoded.iter().zip(&colours).take(checked) {
+                    assert_eq!(*delay, 4);
+                    // Alpha is dropped
+                    let opaque = solid(dims, [colour[0], colour[1], colour[2], 255]);
+                    assert!(close(rgba, &opaque, 8), "{:?} {:?}", dither, rgba);
+                }
+            }
+        }
+    }
+}
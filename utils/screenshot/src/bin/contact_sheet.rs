@@ -0,0 +1,109 @@
+//! Builds a contact sheet of the images in a capture directory.
+//!
+//! ```text
+//! contact_sheet <dir> [-o <out>] [--html] [--columns <n>] [--size <px>] [--caption <key>]
+//! ```
+//!
+//! Writes `<dir>/contact_sheet.png`, or `<dir>/index.html` with `--html`,
+//! unless `-o` says otherwise. `--caption` names a metadata value such as
+//! `seed` to show instead of the file name.
+
+use screenshot::{Caption, ContactSheet, Thumbnail};
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str = "usage: contact_sheet <dir> [-o <out>] [--html] [--columns <n>] \
+                     [--size <px>] [--caption <key>]";
+
+struct Args {
+    dir: PathBuf,
+    out: Option<PathBuf>,
+    html: bool,
+    columns: usize,
+    size: usize,
+    caption: Caption,
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("contact_sheet: {}\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    let name = if args.html {
+        "index.html"
+    } else {
+        "contact_sheet.png"
+    };
+    let out = match args.out {
+        Some(out) => out,
+        None => args.dir.join(name),
+    };
+    let mut sheet = ContactSheet::new()
+        .columns(args.columns)
+        .thumbnail(Thumbnail::new(args.size, args.size))
+        .caption(args.caption);
+    if let Err(e) = sheet.add_dir(&args.dir) {
+        eprintln!("contact_sheet: {}: {}", args.dir.display(), e);
+        process::exit(1);
+    }
+    // A sheet written into the scanned directory under another name
+    sheet.remove(&out);
+    let written = if args.html {
+        sheet.write_html(&out)
+    } else {
+        sheet.write_png(&out).map(|skipped| {
+            for (path, e) in skipped {
+                eprintln!("contact_sheet: skipped {}: {}", path.display(), e);
+            }
+        })
+    };
+    match written {
+        Ok(()) => println!("{} images in {}", sheet.len(), out.display()),
+        Err(e) => {
+            eprintln!("contact_sheet: {}: {}", out.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let mut dir = None;
+    let mut parsed = Args {
+        dir: PathBuf::new(),
+        out: None,
+        html: false,
+        columns: 6,
+        size: 256,
+        caption: Caption::FileName,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "-o" => parsed.out = Some(PathBuf::from(value()?)),
+            "--html" => parsed.html = true,
+            "--columns" => parsed.columns = number(&value()?)?,
+            "--size" => parsed.size = number(&value()?)?,
+            "--caption" => parsed.caption = Caption::Metadata(value()?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if dir.is_none() => dir = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    parsed.dir = dir.ok_or("missing directory")?;
+    Ok(parsed)
+}
+
+fn number(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("{} is not a positive number", value)),
+    }
+}
@@ -1,9 +1,20 @@
-use super::{new_input_image, new_output_image, Buffer};
+use super::color::{PushConstants, Transfer};
+use super::error::ScreenshotError;
+use super::format::SampleFormat;
+use super::{
+    new_frame_copy, new_input_image, new_output_image, output_image_format, Buffer, PixelBuffer,
+};
 use nannou::prelude::*;
 use std::cell::RefCell;
 use std::sync::Arc;
 
 pub(crate) struct FrameCapture {
+    device: Arc<vk::Device>,
+    msaa_samples: u32,
+    sample: SampleFormat,
+    transfer: Transfer,
+    /// The window frame, set aside while the frame renders something else.
+    saved_frame: RefCell<Option<Arc<vk::AttachmentImage>>>,
     resolve_rp: Arc<dyn vk::RenderPassAbstract + Send + Sync>,
     sample_rp: Arc<dyn vk::RenderPassAbstract + Send + Sync>,
     resolve_fbo: RefCell<vk::Fbo>,
@@ -27,91 +38,204 @@ struct Vertex {
 vk::impl_vertex!(Vertex, position);
 
 impl FrameCapture {
-    pub(crate) fn new(device: Arc<vk::Device>, msaa_samples: u32, dims: [u32; 2]) -> Self {
-        let (resolve_rp, sample_rp) = create_render_pass(device.clone(), msaa_samples);
-        let sample_pipeline = create_sample_pipeline(device.clone(), sample_rp.clone());
+    pub(crate) fn new(
+        device: Arc<vk::Device>,
+        msaa_samples: u32,
+        dims: [u32; 2],
+        sample: SampleFormat,
+    ) -> Result<Self, ScreenshotError> {
+        let resolve_rp = create_resolve_render_pass(device.clone(), msaa_samples)?;
+        let sample_rp = create_sample_render_pass(device.clone(), sample)?;
+        let sample_pipeline = create_sample_pipeline(device.clone(), sample_rp.clone(), sample)?;
         let descriptor_set = create_descriptor_set(sample_pipeline.clone());
         let descriptor_set = RefCell::new(descriptor_set);
-        let sampler = vk::SamplerBuilder::new().build(device.clone()).unwrap();
-        FrameCapture {
+        let sampler = vk::SamplerBuilder::new()
+            .build(device.clone())
+            .map_err(ScreenshotError::vulkan("Failed to create sampler"))?;
+        Ok(FrameCapture {
+            device: device.clone(),
+            msaa_samples,
+            sample,
+            transfer: Transfer::default(),
+            saved_frame: RefCell::new(None),
             resolve_rp,
             sample_rp,
             resolve_fbo: Default::default(),
             sample_fbo: Default::default(),
-            inter_color: new_input_image(device.clone(), dims),
+            inter_color: new_input_image(device.clone(), dims)?,
             descriptor_set,
             sample_pipeline,
             sampler,
-            vertex_buffer: create_vertex_buffer(device.clone()),
-            output_image: new_output_image(device.clone(), dims),
-        }
+            vertex_buffer: create_vertex_buffer(device.clone())?,
+            output_image: new_output_image(device.clone(), dims, sample)?,
+        })
+    }
+
+    pub(crate) fn capture(
+        &self,
+        frame: &Frame,
+        screenshot_buffer: Buffer,
+    ) -> Result<(), ScreenshotError> {
+        self.resolve(frame)?;
+        self.read_back(frame, screenshot_buffer)
     }
 
-    pub(crate) fn capture(&self, frame: &Frame, screenshot_buffer: Buffer) {
+    /// Copies the frame into the intermediate image, resolving it if needed.
+    pub(crate) fn resolve(&self, frame: &Frame) -> Result<(), ScreenshotError> {
         let [w, h] = frame.swapchain_image().dimensions();
         let dims = [w, h, 1];
-        let viewport = vk::ViewportBuilder::new().build([w as _, h as _]);
-        let dynamic_state = vk::DynamicState::default().viewports(vec![viewport]);
         // Copy image in a pass so that we can resolve if needed
         self.resolve_fbo
             .borrow_mut()
             .update(self.resolve_rp.clone(), dims, |builder| {
                 builder
-                    .add(frame.image().clone())
-                    .expect("Failed to add frame image")
+                    .add(frame.image().clone())?
                     .add(self.inter_color.clone())
             })
-            .expect("Failed to add inter image");
+            .map_err(ScreenshotError::vulkan("Failed to add inter image"))?;
+        let clear_values = vec![vk::ClearValue::None, vk::ClearValue::None];
+        let resolve_fbo = self
+            .resolve_fbo
+            .borrow()
+            .as_ref()
+            .ok_or_else(missing_fbo)?
+            .clone();
+        frame
+            .add_commands()
+            .begin_render_pass(resolve_fbo, clear_values)
+            .map_err(ScreenshotError::vulkan(
+                "failed to begin render pass for screenshot copy",
+            ))?
+            .end_render_pass()
+            .map_err(ScreenshotError::vulkan(
+                "failed to add `end_render_pass` command",
+            ))?;
+        Ok(())
+    }
+
+    /// Copies `extent` pixels of the intermediate image into the one of
+    /// `target` at `offset`, to assemble an image larger than the frame.
+    pub(crate) fn copy_to(
+        &self,
+        frame: &Frame,
+        target: &FrameCapture,
+        offset: [u32; 2],
+        extent: [u32; 2],
+    ) -> Result<(), ScreenshotError> {
+        frame
+            .add_commands()
+            .copy_image(
+                self.inter_color.clone(),
+                [0, 0, 0],
+                0,
+                0,
+                target.inter_color.clone(),
+                [offset[0] as i32, offset[1] as i32, 0],
+                0,
+                0,
+                [extent[0], extent[1], 1],
+                1,
+            )
+            .map_err(ScreenshotError::vulkan(
+                "Failed to copy into offscreen image",
+            ))?;
+        Ok(())
+    }
+
+    /// Copies what was drawn to `frame` so far aside, so the frame can
+    /// render something else before `restore_frame` puts it back.
+    pub(crate) fn save_frame(&self, frame: &Frame) -> Result<(), ScreenshotError> {
+        let [w, h] = frame.image().dimensions();
+        let mut saved = self.saved_frame.borrow_mut();
+        let stale = saved
+            .as_ref()
+            .map_or(true, |image| image.dimensions() != [w, h]);
+        if stale {
+            *saved = Some(new_frame_copy(
+                self.device.clone(),
+                [w, h],
+                self.msaa_samples,
+            )?);
+        }
+        let saved = saved.as_ref().ok_or_else(missing_fbo)?;
+        copy_image(frame, frame.image().clone(), saved.clone(), [w, h])
+    }
+
+    /// Replaces the contents of `frame` with the copy made by `save_frame`.
+    pub(crate) fn restore_frame(&self, frame: &Frame) -> Result<(), ScreenshotError> {
+        let saved = self.saved_frame.borrow();
+        let saved = saved.as_ref().ok_or_else(missing_fbo)?;
+        copy_image(
+            frame,
+            saved.clone(),
+            frame.image().clone(),
+            saved.dimensions(),
+        )
+    }
+
+    /// Encodes the intermediate image in the sample pass
+    /// and copies the result into `screenshot_buffer`.
+    pub(crate) fn read_back(
+        &self,
+        frame: &Frame,
+        screenshot_buffer: Buffer,
+    ) -> Result<(), ScreenshotError> {
+        let [w, h] = self.output_image.dimensions();
+        let dims = [w, h, 1];
+        let max = match self.sample {
+            SampleFormat::U8 => 255.0,
+            SampleFormat::U16 => 65535.0,
+            SampleFormat::F32 => 0.0,
+        };
+        let push_constants = PushConstants::new(self.transfer, max);
+        let viewport = vk::ViewportBuilder::new().build([w as _, h as _]);
+        let dynamic_state = vk::DynamicState::default().viewports(vec![viewport]);
         self.sample_fbo
             .borrow_mut()
             .update(self.sample_rp.clone(), dims, |builder| {
                 builder.add(self.output_image.clone())
             })
-            .expect("Failed to add output image");
-        let clear_values = vec![vk::ClearValue::None, vk::ClearValue::None];
+            .map_err(ScreenshotError::vulkan("Failed to add output image"))?;
         let clear_value_sample = vec![vk::ClearValue::None];
         let set = self
             .descriptor_set
             .borrow_mut()
             .next()
             .add_sampled_image(self.inter_color.clone(), self.sampler.clone())
-            .expect("Failed to add sampler")
+            .map_err(ScreenshotError::vulkan("Failed to add sampler"))?
             .build()
-            .expect("Failed to build descriptor set");
-        frame
+            .map_err(ScreenshotError::vulkan("Failed to build descriptor set"))?;
+        let sample_fbo = self
+            .sample_fbo
+            .borrow()
+            .as_ref()
+            .ok_or_else(missing_fbo)?
+            .clone();
+        let commands = frame
             .add_commands()
-            .begin_render_pass(
-                self.resolve_fbo
-                    .borrow()
-                    .as_ref()
-                    .expect("Failed to get resolve_fbo")
-                    .clone(),
-                clear_values.clone(),
-            )
-            .expect("failed to begin render pass for screenshot copy")
-            .end_render_pass()
-            .expect("failed to add `end_render_pass` command")
-            .begin_render_pass(
-                self.sample_fbo
-                    .borrow()
-                    .as_ref()
-                    .expect("Failed to get resolve_fbo")
-                    .clone(),
-                clear_value_sample,
-            )
-            .expect("failed to begin render pass for screenshot copy")
+            .begin_render_pass(sample_fbo, clear_value_sample)
+            .map_err(ScreenshotError::vulkan(
+                "failed to begin render pass for screenshot copy",
+            ))?
             .draw(
                 self.sample_pipeline.clone(),
                 &dynamic_state,
                 vec![self.vertex_buffer.clone()],
                 set,
-                (),
+                push_constants,
             )
-            .expect("Failed to draw sample pass")
+            .map_err(ScreenshotError::vulkan("Failed to draw sample pass"))?
             .end_render_pass()
-            .expect("failed to add `end_render_pass` command")
-            .copy_image_to_buffer(self.output_image.clone(), screenshot_buffer.buffer.clone())
-            .expect("Failed to copy image to buffer");
+            .map_err(ScreenshotError::vulkan(
+                "failed to add `end_render_pass` command",
+            ))?;
+        let output_image = self.output_image.clone();
+        match screenshot_buffer.buffer {
+            PixelBuffer::U8(buffer) => commands.copy_image_to_buffer(output_image, buffer),
+            PixelBuffer::U16(buffer) => commands.copy_image_to_buffer(output_image, buffer),
+            PixelBuffer::F32(buffer) => commands.copy_image_to_buffer(output_image, buffer),
+        }
+        .map_err(ScreenshotError::vulkan("Failed to copy image to buffer"))?;
         /*
         let [w, h] = self.inter_color.dimensions();
         let src = self.inter_color.clone();
@@ -144,6 +268,7 @@ impl FrameCapture {
             )
             .expect("failed to blit linear sRGBA image to swapchain image")
             */
+        Ok(())
     }
 
     pub(crate) fn clear(&self) {
@@ -151,19 +276,90 @@ impl FrameCapture {
         *fb = Default::default();
     }
 
-    pub(crate) fn update_images(&mut self, device: Arc<vk::Device>, dims: (usize, usize)) {
-        self.inter_color = new_input_image(device.clone(), [dims.0 as u32, dims.1 as u32]);
-        self.output_image = new_output_image(device.clone(), [dims.0 as u32, dims.1 as u32]);
+    pub(crate) fn update_images(
+        &mut self,
+        device: Arc<vk::Device>,
+        dims: (usize, usize),
+    ) -> Result<(), ScreenshotError> {
+        self.inter_color = new_input_image(device.clone(), [dims.0 as u32, dims.1 as u32])?;
+        self.output_image =
+            new_output_image(device.clone(), [dims.0 as u32, dims.1 as u32], self.sample)?;
+        Ok(())
+    }
+
+    pub(crate) fn dims(&self) -> (usize, usize) {
+        let [w, h] = self.output_image.dimensions();
+        (w as usize, h as usize)
+    }
+
+    pub(crate) fn sample(&self) -> SampleFormat {
+        self.sample
+    }
+
+    pub(crate) fn transfer(&self) -> Transfer {
+        self.transfer
+    }
+
+    /// Sets the encoding applied by the sample pass.
+    pub(crate) fn set_transfer(&mut self, transfer: Transfer) {
+        self.transfer = transfer;
+    }
+
+    /// Rebuilds the sample pass to read frames back at a different precision.
+    pub(crate) fn set_sample_format(
+        &mut self,
+        sample: SampleFormat,
+    ) -> Result<(), ScreenshotError> {
+        let device = self.device.clone();
+        let [w, h] = self.output_image.dimensions();
+        let sample_rp = create_sample_render_pass(device.clone(), sample)?;
+        let sample_pipeline = create_sample_pipeline(device.clone(), sample_rp.clone(), sample)?;
+        self.output_image = new_output_image(device, [w, h], sample)?;
+        self.descriptor_set = RefCell::new(create_descriptor_set(sample_pipeline.clone()));
+        self.sample_rp = sample_rp;
+        self.sample_pipeline = sample_pipeline;
+        self.sample_fbo = Default::default();
+        self.sample = sample;
+        Ok(())
+    }
+}
+
+/// Copies the whole of `src` into `dst`, which have the same size and samples.
+fn copy_image(
+    frame: &Frame,
+    src: Arc<vk::AttachmentImage>,
+    dst: Arc<vk::AttachmentImage>,
+    extent: [u32; 2],
+) -> Result<(), ScreenshotError> {
+    frame
+        .add_commands()
+        .copy_image(
+            src,
+            [0, 0, 0],
+            0,
+            0,
+            dst,
+            [0, 0, 0],
+            0,
+            0,
+            [extent[0], extent[1], 1],
+            1,
+        )
+        .map_err(ScreenshotError::vulkan("Failed to copy the window frame"))?;
+    Ok(())
+}
+
+fn missing_fbo() -> ScreenshotError {
+    ScreenshotError::Vulkan {
+        context: "Failed to get framebuffer",
+        message: "framebuffer was not created".to_string(),
     }
 }
 
-fn create_render_pass(
+fn create_resolve_render_pass(
     device: Arc<vk::Device>,
     msaa_samples: u32,
-) -> (
-    Arc<dyn vk::RenderPassAbstract + Send + Sync>,
-    Arc<dyn vk::RenderPassAbstract + Send + Sync>,
-) {
+) -> Result<Arc<dyn vk::RenderPassAbstract + Send + Sync>, ScreenshotError> {
     let resolve_rp = vk::single_pass_renderpass!(
         device.clone(),
         attachments: {
@@ -189,14 +385,23 @@ fn create_render_pass(
             resolve: [resolve_image],
         }
     )
-    .expect("Failed to create resolve renderpass");
+    .map_err(ScreenshotError::vulkan(
+        "Failed to create resolve renderpass",
+    ))?;
+    Ok(Arc::new(resolve_rp))
+}
+
+fn create_sample_render_pass(
+    device: Arc<vk::Device>,
+    sample: SampleFormat,
+) -> Result<Arc<dyn vk::RenderPassAbstract + Send + Sync>, ScreenshotError> {
     let sample_rp = vk::single_pass_renderpass!(
         device,
         attachments: {
             output_image: {
                 load: DontCare,
                 store: Store,
-                format: vk::Format::R8G8B8A8Uint,
+                format: output_image_format(sample),
                 samples: 1,
             }
         },
@@ -206,7 +411,9 @@ fn create_render_pass(
             resolve: [],
         }
     )
-    .expect("Failed to create resolve renderpass");
+    .map_err(ScreenshotError::vulkan(
+        "Failed to create sample renderpass",
+    ))?;
     /*
     let rp = vulkano::ordered_passes_renderpass!(
         device,
@@ -250,32 +457,52 @@ fn create_render_pass(
     .expect("Failed to create resolve renderpass");
     let rp = Arc::new(rp) as Arc<dyn vk::RenderPassAbstract + Send + Sync>;
     rp*/
-    (Arc::new(resolve_rp), Arc::new(sample_rp))
+    Ok(Arc::new(sample_rp))
 }
 
 fn create_sample_pipeline(
     device: Arc<vk::Device>,
     render_pass: Arc<dyn vk::RenderPassAbstract + Send + Sync>,
-) -> Arc<dyn vk::GraphicsPipelineAbstract + Send + Sync> {
-    let vertex_shader = vs::Shader::load(device.clone()).unwrap();
-    let fragment_shader = fs::Shader::load(device.clone()).unwrap();
-    Arc::new(
-        vk::GraphicsPipeline::start()
-            .vertex_input_single_buffer::<Vertex>()
-            .vertex_shader(vertex_shader.main_entry_point(), ())
-            .triangle_strip()
-            .viewports_dynamic_scissors_irrelevant(1)
-            .fragment_shader(fragment_shader.main_entry_point(), ())
-            .render_pass(vk::Subpass::from(render_pass, 0).unwrap())
-            .build(device)
-            .unwrap(),
-    )
+    sample: SampleFormat,
+) -> Result<Arc<dyn vk::GraphicsPipelineAbstract + Send + Sync>, ScreenshotError> {
+    // Each fragment shader is its own type so the
+    // pipeline has to be built once per module
+    macro_rules! pipeline {
+        ($fs:ident) => {{
+            let vertex_shader = vs::Shader::load(device.clone())
+                .map_err(ScreenshotError::vulkan("Failed to load vertex shader"))?;
+            let fragment_shader = $fs::Shader::load(device.clone())
+                .map_err(ScreenshotError::vulkan("Failed to load fragment shader"))?;
+            let subpass = vk::Subpass::from(render_pass, 0).ok_or(ScreenshotError::Vulkan {
+                context: "Failed to create sample pipeline",
+                message: "render pass has no subpass 0".to_string(),
+            })?;
+            Arc::new(
+                vk::GraphicsPipeline::start()
+                    .vertex_input_single_buffer::<Vertex>()
+                    .vertex_shader(vertex_shader.main_entry_point(), ())
+                    .triangle_strip()
+                    .viewports_dynamic_scissors_irrelevant(1)
+                    .fragment_shader(fragment_shader.main_entry_point(), ())
+                    .render_pass(subpass)
+                    .build(device)
+                    .map_err(ScreenshotError::vulkan("Failed to create sample pipeline"))?,
+            ) as Arc<dyn vk::GraphicsPipelineAbstract + Send + Sync>
+        }};
+    }
+    Ok(match sample {
+        SampleFormat::U8 | SampleFormat::U16 => pipeline!(fs),
+        SampleFormat::F32 => pipeline!(fs_float),
+    })
 }
 
-fn create_vertex_buffer(device: Arc<vk::Device>) -> Arc<vk::CpuAccessibleBuffer<[Vertex]>> {
+fn create_vertex_buffer(
+    device: Arc<vk::Device>,
+) -> Result<Arc<vk::CpuAccessibleBuffer<[Vertex]>>, ScreenshotError> {
     let positions = [[-1.0, -1.0], [-1.0, 1.0], [1.0, -1.0], [1.0, 1.0]];
     let vertices = positions.iter().map(|&position| Vertex { position });
-    vk::CpuAccessibleBuffer::from_iter(device.clone(), vk::BufferUsage::all(), vertices).unwrap()
+    vk::CpuAccessibleBuffer::from_iter(device.clone(), vk::BufferUsage::all(), vertices)
+        .map_err(ScreenshotError::vulkan("Failed to create vertex buffer"))
 }
 
 fn create_descriptor_set(
@@ -299,6 +526,8 @@ void main() {
     }
 }
 
+// Encodes the linear frame and scales it to the integer
+// range of the output image given by `pc.max`
 mod fs {
     nannou::vk::shaders::shader! {
     ty: "fragment",
@@ -307,14 +536,62 @@ mod fs {
 layout(location = 0) in vec2 tex_coords;
 layout(location = 0) out uvec4 f_color;
 layout(set = 0, binding = 0) uniform sampler2D tex;
+layout(push_constant) uniform Pipeline {
+    uint transfer;
+    float gamma;
+    float max;
+} pc;
+
+// Must match `Transfer::encode`
+vec3 encode(vec3 c) {
+    if (pc.transfer == 1u) {
+        c = max(c, 0.0);
+        return mix(12.92 * c, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, greaterThan(c, vec3(0.0031308)));
+    }
+    if (pc.transfer == 2u) {
+        return pow(max(c, 0.0), vec3(1.0 / pc.gamma));
+    }
+    return c;
+}
+
+void main() {
+    vec4 col = texture(tex, tex_coords);
+    col.rgb = encode(col.rgb);
+    f_color = uvec4(clamp(col, 0.0, 1.0) * pc.max + 0.5);
+}"
+    }
+}
+
+// Float output keeps values outside 0..1, e.g. for EXR
+mod fs_float {
+    nannou::vk::shaders::shader! {
+    ty: "fragment",
+        src: "
+#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 0) out vec4 f_color;
+layout(set = 0, binding = 0) uniform sampler2D tex;
+layout(push_constant) uniform Pipeline {
+    uint transfer;
+    float gamma;
+    float max;
+} pc;
+
+// Must match `Transfer::encode`
+vec3 encode(vec3 c) {
+    if (pc.transfer == 1u) {
+        c = max(c, 0.0);
+        return mix(12.92 * c, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, greaterThan(c, vec3(0.0031308)));
+    }
+    if (pc.transfer == 2u) {
+        return pow(max(c, 0.0), vec3(1.0 / pc.gamma));
+    }
+    return c;
+}
+
 void main() {
-    float max = 255;
     vec4 col = texture(tex, tex_coords);
-    f_color = uvec4(
-    uint(max * pow(col.r, 1.0 / 2.2)),
-    uint(max * pow(col.g, 1.0 / 2.2)),
-    uint(max * pow(col.b, 1.0 / 2.2)),
-    uint(max * col.a));
+    f_color = vec4(encode(col.rgb), col.a);
 }"
     }
 }
@@ -0,0 +1,182 @@
+/// Encoding applied to the linear colour of the frame before it is saved.
+///
+/// Alpha is never encoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transfer {
+    /// Values are saved as rendered. Use this for float formats, or when
+    /// the sketch already draws sRGB encoded colours.
+    Linear,
+    /// The piecewise sRGB OETF from IEC 61966-2-1.
+    Srgb,
+    /// A pure power curve, `v^(1/gamma)`.
+    Gamma(f32),
+}
+
+/// Where the transfer function of `Shots` is applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferStage {
+    /// In the readback shader, the default.
+    Gpu,
+    /// On the saving thread, reading back linear floats so no precision is
+    /// lost before encoding. Frees GPU time at the cost of 4 byte samples.
+    Cpu,
+}
+
+impl Transfer {
+    /// Encodes one linear colour value.
+    ///
+    /// Negative values are clamped to 0 by the non-linear curves,
+    /// values above 1 are left for the output format to clamp.
+    ///
+    /// ```
+    /// use screenshot::Transfer;
+    ///
+    /// assert_eq!(Transfer::Srgb.encode(0.0), 0.0);
+    /// assert!((Transfer::Srgb.encode(0.5) - 0.735_357).abs() < 1e-6);
+    /// ```
+    pub fn encode(self, v: f32) -> f32 {
+        match self {
+            Transfer::Linear => v,
+            Transfer::Srgb => {
+                let v = v.max(0.0);
+                if v <= 0.003_130_8 {
+                    12.92 * v
+                } else {
+                    1.055 * v.powf(1.0 / 2.4) - 0.055
+                }
+            }
+            Transfer::Gamma(gamma) => v.max(0.0).powf(1.0 / gamma),
+        }
+    }
+
+    /// Encodes the colour channels of linear RGBA pixels in place.
+    pub fn encode_rgba(self, pixels: &mut [f32]) {
+        if self == Transfer::Linear {
+            return;
+        }
+        for pixel in pixels.chunks_exact_mut(4) {
+            for c in &mut pixel[..3] {
+                *c = self.encode(*c);
+            }
+        }
+    }
+
+    /// Encodes native endian RGBA float samples as read back from the GPU.
+    pub(crate) fn encode_f32_bytes(self, data: &[u8]) -> Vec<u8> {
+        let mut out = data.to_vec();
+        for (i, b) in out.chunks_exact_mut(4).enumerate() {
+            if i % 4 != 3 {
+                let v = f32::from_ne_bytes([b[0], b[1], b[2], b[3]]);
+                b.copy_from_slice(&self.encode(v).to_ne_bytes());
+            }
+        }
+        out
+    }
+}
+
+impl Default for Transfer {
+    fn default() -> Self {
+        Transfer::Srgb
+    }
+}
+
+impl Default for TransferStage {
+    fn default() -> Self {
+        TransferStage::Gpu
+    }
+}
+
+/// Push constants shared by the readback shaders. The layout
+/// must match the `Pipeline` block declared in `capture.rs`.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub(crate) struct PushConstants {
+    transfer: u32,
+    gamma: f32,
+    /// Largest integer sample, or 0 for float output.
+    max: f32,
+}
+
+impl PushConstants {
+    pub(crate) fn new(transfer: Transfer, max: f32) -> Self {
+        let (transfer, gamma) = match transfer {
+            Transfer::Linear => (0, 1.0),
+            Transfer::Srgb => (1, 1.0),
+            Transfer::Gamma(gamma) => (2, gamma),
+        };
+        PushConstants {
+            transfer,
+            gamma,
+            max,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    /// The inverse of the sRGB curve, from IEC 61966-2-1.
+    fn srgb_decode(v: f32) -> f32 {
+        if v <= 0.040_45 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        }
+    }
+
+    #[test]
+    fn srgb_is_continuous_at_the_breakpoint() {
+        let below = Transfer::Srgb.encode(0.003_130_8);
+        let above = Transfer::Srgb.encode(0.003_130_9);
+        assert!(close(below, 12.92 * 0.003_130_8));
+        assert!(close(below, 0.040_45));
+        assert!(above > below && above - below < 1e-5);
+    }
+
+    #[test]
+    fn srgb_known_values() {
+        for &(linear, encoded) in &[
+            (0.0, 0.0),
+            (0.001, 0.012_92),
+            (0.18, 0.461_356),
+            (0.214_041, 0.5),
+            (0.5, 0.735_357),
+            (1.0, 1.0),
+        ] {
+            assert!(close(Transfer::Srgb.encode(linear), encoded), "{}", linear);
+        }
+        assert_eq!(Transfer::Srgb.encode(-1.0), 0.0);
+    }
+
+    #[test]
+    fn srgb_round_trips() {
+        for i in 0..=255 {
+            let encoded = i as f32 / 255.0;
+            let round_trip = Transfer::Srgb.encode(srgb_decode(encoded));
+            assert!((round_trip - encoded).abs() < 1e-4, "{}", i);
+        }
+    }
+
+    #[test]
+    fn gamma_is_a_power_curve() {
+        assert!(close(Transfer::Gamma(2.2).encode(0.5), 0.729_740));
+        assert!(close(Transfer::Gamma(2.0).encode(0.25), 0.5));
+        assert!(close(Transfer::Gamma(1.0).encode(0.3), 0.3));
+        assert_eq!(Transfer::Gamma(2.2).encode(1.0), 1.0);
+        assert_eq!(Transfer::Gamma(2.2).encode(-0.5), 0.0);
+    }
+
+    #[test]
+    fn alpha_is_not_encoded() {
+        let mut pixels = [0.5, 0.5, 0.5, 0.5];
+        Transfer::Gamma(2.0).encode_rgba(&mut pixels);
+        assert!(close(pixels[0], 0.5f32.sqrt()));
+        assert_eq!(pixels[3], 0.5);
+        assert_eq!(Transfer::Linear.encode(-0.5), -0.5);
+    }
+}
@@ -0,0 +1,428 @@
+use super::format::{self, OutputFormat, SampleFormat};
+use super::metadata::{self, Metadata};
+use super::ora::escape;
+use super::region::Thumbnail;
+use nannou::image::{self, Rgba, RgbaImage};
+use nannou::text::{font, rt};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Space around every cell and between rows, in pixels.
+const PADDING: usize = 8;
+/// Height of the caption line below each thumbnail.
+const CAPTION_HEIGHT: usize = 20;
+const FONT_SIZE: f32 = 14.0;
+const BACKGROUND: [u8; 3] = [32, 32, 32];
+const TEXT: [u8; 3] = [220, 220, 220];
+
+/// What is written below each image of a `ContactSheet`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Caption {
+    None,
+    /// The path relative to the scanned directory, e.g. `subdir1/screenshot3.png`.
+    FileName,
+    /// A metadata value, such as `seed`, read from the PNG text chunks or
+    /// the JSON sidecar. Images without it fall back to their file name.
+    Metadata(String),
+}
+
+/// An overview of the images in a capture directory, written as a grid
+/// of thumbnails with captions to one PNG or as a static HTML page.
+///
+/// ```no_run
+/// # use screenshot::{Caption, ContactSheet};
+/// # use std::path::Path;
+/// # fn main() -> std::io::Result<()> {
+/// let mut sheet = ContactSheet::new().columns(8).caption(Caption::Metadata("seed".into()));
+/// sheet.add_dir(Path::new("dist"))?;
+/// sheet.write_png(Path::new("dist/contact_sheet.png"))?;
+/// sheet.write_html(Path::new("dist/index.html"))?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ContactSheet {
+    columns: usize,
+    thumbnail: Thumbnail,
+    caption: Caption,
+    images: Vec<Entry>,
+}
+
+struct Entry {
+    path: PathBuf,
+    caption: String,
+}
+
+impl ContactSheet {
+    /// Six columns of 256 pixel thumbnails, captioned with their file names.
+    pub fn new() -> Self {
+        ContactSheet {
+            columns: 6,
+            thumbnail: Thumbnail::new(256, 256),
+            caption: Caption::FileName,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn columns(mut self, columns: usize) -> Self {
+        self.columns = columns.max(1);
+        self
+    }
+
+    /// Size and filter of the thumbnails in the PNG sheet,
+    /// also used as the image size in the HTML page.
+    pub fn thumbnail(mut self, thumbnail: Thumbnail) -> Self {
+        self.thumbnail = thumbnail;
+        self
+    }
+
+    /// Applies to images added afterwards.
+    pub fn caption(mut self, caption: Caption) -> Self {
+        self.caption = caption;
+        self
+    }
+
+    /// Number of images on the sheet.
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    /// Adds every image in `dir` and its subdirectories, ordered by path
+    /// with numbers compared by value, so `screenshot10` follows `screenshot9`.
+    /// Thumbnails written by `Shots::thumbnails` and earlier sheets
+    /// named `contact_sheet*.png` are left out.
+    pub fn add_dir(&mut self, dir: &Path) -> io::Result<()> {
+        let mut paths = Vec::new();
+        find_images(dir, &mut paths)?;
+        paths.sort_by_cached_key(|path| natural_key(&path.to_string_lossy()));
+        for path in paths {
+            let name = path.strip_prefix(dir).unwrap_or(&path);
+            let name = name.to_string_lossy().replace('\\', "/");
+            self.add_image(&path, &name);
+        }
+        Ok(())
+    }
+
+    /// Adds one image, captioned with `name` unless the caption comes from metadata.
+    pub fn add_image(&mut self, path: &Path, name: &str) {
+        let caption = match &self.caption {
+            Caption::None => String::new(),
+            Caption::FileName => name.to_string(),
+            Caption::Metadata(key) => read_metadata(path)
+                .remove(key)
+                .unwrap_or_else(|| name.to_string()),
+        };
+        self.images.push(Entry {
+            path: path.to_path_buf(),
+            caption,
+        });
+    }
+
+    /// Takes the image at `path` off the sheet, such as
+    /// a previous sheet about to be overwritten.
+    pub fn remove(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.images.retain(|entry| {
+            entry
+                .path
+                .canonicalize()
+                .unwrap_or_else(|_| entry.path.clone())
+                != path
+        });
+    }
+
+    /// Draws the sheet into one PNG. Images that cannot be
+    /// decoded are left out and returned with their errors.
+    pub fn write_png(&self, path: &Path) -> io::Result<Vec<(PathBuf, io::Error)>> {
+        let cell = (
+            self.thumbnail.width + PADDING,
+            self.thumbnail.height + CAPTION_HEIGHT + PADDING,
+        );
+        let rows = (self.images.len() + self.columns - 1) / self.columns;
+        let cols = self.columns.min(self.images.len()).max(1);
+        let dims = (cols * cell.0 + PADDING, rows.max(1) * cell.1 + PADDING);
+        let [r, g, b] = BACKGROUND;
+        let mut sheet = RgbaImage::from_pixel(dims.0 as u32, dims.1 as u32, Rgba([r, g, b, 255]));
+        let font = font::default_notosans();
+        let mut skipped = Vec::new();
+        for (i, entry) in self.images.iter().enumerate() {
+            let x = PADDING + i % self.columns * cell.0;
+            let y = PADDING + i / self.columns * cell.1;
+            match self.load_thumbnail(&entry.path) {
+                Ok((thumbnail, (w, h))) => {
+                    // Centred in the space for the largest thumbnail
+                    let left = x + (self.thumbnail.width - w) / 2;
+                    let top = y + (self.thumbnail.height - h) / 2;
+                    paste(&mut sheet, &thumbnail, (w, h), (left, top));
+                }
+                Err(e) => skipped.push((entry.path.clone(), e)),
+            }
+            let caption_top = y + self.thumbnail.height;
+            draw_caption(
+                &mut sheet,
+                &font,
+                &entry.caption,
+                (x, caption_top),
+                self.thumbnail.width,
+            );
+        }
+        format::write_image(path, &sheet, dims, SampleFormat::U8, OutputFormat::Png)?;
+        Ok(skipped)
+    }
+
+    /// Writes a page that links every image, shown in a grid at the
+    /// thumbnail size. Image paths are relative to the page where possible.
+    pub fn write_html(&self, path: &Path) -> io::Result<()> {
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let base = fs::canonicalize(base).unwrap_or_else(|_| base.to_path_buf());
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        html.push_str("<title>Contact sheet</title>\n<style>\n");
+        let (w, h) = (self.thumbnail.width, self.thumbnail.height);
+        write!(
+            html,
+            "body {{ background: #202020; color: #dcdcdc; font-family: sans-serif; }}\n\
+             main {{ display: grid; grid-template-columns: repeat({}, {}px); gap: {}px; }}\n\
+             figure {{ margin: 0; }}\n\
+             img {{ width: {}px; height: {}px; object-fit: contain; }}\n\
+             figcaption {{ font-size: 14px; overflow: hidden; white-space: nowrap; }}\n",
+            self.columns, w, PADDING, w, h
+        )
+        .ok();
+        html.push_str("</style>\n</head>\n<body>\n<main>\n");
+        for entry in &self.images {
+            let image = fs::canonicalize(&entry.path).unwrap_or_else(|_| entry.path.clone());
+            let src = image.strip_prefix(&base).unwrap_or(&image);
+            let src = escape(&url_path(&src.to_string_lossy()));
+            let caption = escape(&entry.caption);
+            write!(
+                html,
+                "<figure><a href=\"{}\"><img src=\"{}\" alt=\"{}\" loading=\"lazy\"></a>\
+                 <figcaption>{}</figcaption></figure>\n",
+                src, src, caption, caption
+            )
+            .ok();
+        }
+        html.push_str("</main>\n</body>\n</html>\n");
+        fs::write(path, html)
+    }
+
+    fn load_thumbnail(&self, path: &Path) -> io::Result<(Vec<u8>, (usize, usize))> {
+        let image = image::open(path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+            .to_rgba();
+        let dims = (image.width() as usize, image.height() as usize);
+        self.thumbnail.shrink(&image, dims, SampleFormat::U8)
+    }
+}
+
+impl Default for ContactSheet {
+    fn default() -> Self {
+        ContactSheet::new()
+    }
+}
+
+fn find_images(dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_images(&path, paths)?;
+            continue;
+        }
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let image = match extension.to_lowercase().as_str() {
+            "png" | "jpg" | "jpeg" | "bmp" | "tif" | "tiff" => true,
+            _ => false,
+        };
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let sheet = stem.starts_with("contact_sheet") && extension.eq_ignore_ascii_case("png");
+        if image && !stem.ends_with("_thumb") && !sheet {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Splits `name` into text and numbers, so numbers sort by value.
+fn natural_key(name: &str) -> Vec<(String, u64)> {
+    let mut key = Vec::new();
+    let mut text = String::new();
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !c.is_ascii_digit() {
+            text.push(c);
+            continue;
+        }
+        let mut number = c.to_digit(10).unwrap_or(0) as u64;
+        while let Some(d) = chars.peek().and_then(|d| d.to_digit(10)) {
+            number = number.saturating_mul(10).saturating_add(d as u64);
+            chars.next();
+        }
+        key.push((std::mem::replace(&mut text, String::new()), number));
+    }
+    key.push((text, 0));
+    key
+}
+
+/// Percent-encodes the characters of a file path that end or break a URL.
+fn url_path(path: &str) -> String {
+    let mut url = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '\\' => url.push('/'),
+            '%' => url.push_str("%25"),
+            ' ' => url.push_str("%20"),
+            '#' => url.push_str("%23"),
+            '?' => url.push_str("%3F"),
+            c => url.push(c),
+        }
+    }
+    url
+}
+
+/// Text chunks of PNG files, then the JSON sidecar next to the image.
+fn read_metadata(path: &Path) -> Metadata {
+    let mut found = metadata::read_png_text(path).unwrap_or_default();
+    if let Ok(sidecar) = metadata::read_sidecar(&path.with_extension("json")) {
+        found.extend(sidecar);
+    }
+    found
+}
+
+/// Copies RGBA `data` into the sheet, blending it over the background.
+fn paste(sheet: &mut RgbaImage, data: &[u8], dims: (usize, usize), at: (usize, usize)) {
+    for (i, pixel) in data.chunks_exact(4).enumerate() {
+        let (x, y) = ((at.0 + i % dims.0) as u32, (at.1 + i / dims.0) as u32);
+        if x < sheet.width() && y < sheet.height() {
+            blend(
+                sheet.get_pixel_mut(x, y),
+                [pixel[0], pixel[1], pixel[2]],
+                pixel[3],
+            );
+        }
+    }
+}
+
+/// Draws one line of text at `at`, cut off at `width` pixels.
+fn draw_caption(
+    sheet: &mut RgbaImage,
+    font: &rt::Font<'static>,
+    text: &str,
+    at: (usize, usize),
+    width: usize,
+) {
+    let scale = rt::Scale::uniform(FONT_SIZE);
+    let ascent = font.v_metrics(scale).ascent;
+    let start = rt::point(
+        at.0 as f32,
+        at.1 as f32 + (CAPTION_HEIGHT as f32 + ascent) / 2.0,
+    );
+    let right = (at.0 + width) as i32;
+    let bottom = (at.1 + CAPTION_HEIGHT) as i32;
+    for glyph in font.layout(text, scale, start) {
+        let bounds = match glyph.pixel_bounding_box() {
+            Some(bounds) => bounds,
+            None => continue,
+        };
+        if bounds.max.x > right {
+            break;
+        }
+        glyph.draw(|gx, gy, coverage| {
+            let (x, y) = (bounds.min.x + gx as i32, bounds.min.y + gy as i32);
+            if x >= 0 && y >= 0 && y < bottom {
+                let alpha = (coverage * 255.0).round() as u8;
+                blend(sheet.get_pixel_mut(x as u32, y as u32), TEXT, alpha);
+            }
+        });
+    }
+}
+
+fn blend(dst: &mut Rgba<u8>, src: [u8; 3], alpha: u8) {
+    let a = alpha as u32;
+    for (d, &s) in dst.data.iter_mut().zip(src.iter()) {
+        *d = ((s as u32 * a + *d as u32 * (255 - a) + 127) / 255) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn numbers_sort_by_value() {
+        let mut names = vec![
+            "screenshot10.png",
+            "screenshot9.png",
+            "screenshot1.png",
+            "recording2/screenshot1.png",
+            "recording10/screenshot1.png",
+            "frame_0002.png",
+            "frame_10.png",
+        ];
+        names.sort_by_key(|name| natural_key(name));
+        assert_eq!(
+            names,
+            [
+                "frame_0002.png",
+                "frame_10.png",
+                "recording2/screenshot1.png",
+                "recording10/screenshot1.png",
+                "screenshot1.png",
+                "screenshot9.png",
+                "screenshot10.png",
+            ]
+        );
+    }
+
+    #[test]
+    fn natural_key_parts() {
+        let key = |pairs: &[(&str, u64)]| -> Vec<(String, u64)> {
+            pairs.iter().map(|&(t, n)| (t.to_string(), n)).collect()
+        };
+        assert_eq!(natural_key("a12b3"), key(&[("a", 12), ("b", 3), ("", 0)]));
+        assert_eq!(natural_key("7"), key(&[("", 7), ("", 0)]));
+        assert_eq!(natural_key(""), key(&[("", 0)]));
+        // Too long for u64, but still ordered after smaller numbers
+        assert_eq!(natural_key("99999999999999999999999")[0].1, std::u64::MAX);
+    }
+
+    #[test]
+    fn sheets_thumbnails_and_the_output_are_left_out() {
+        let dir = std::env::temp_dir().join(format!("screenshot-contact-{}", process::id()));
+        fs::create_dir_all(dir.join("recording1")).unwrap();
+        for name in &[
+            "screenshot10.png",
+            "screenshot9.png",
+            "screenshot9_thumb.png",
+            "contact_sheet.png",
+            "contact_sheet-2.png",
+            "contact_sheet.jpg",
+            "notes.txt",
+            "overview.png",
+            "recording1/frame1.TIFF",
+        ] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        let mut sheet = ContactSheet::new();
+        sheet.add_dir(&dir).unwrap();
+        // Written into the scanned directory under another name
+        sheet.remove(&dir.join("overview.png"));
+        let captions: Vec<_> = sheet.images.iter().map(|e| e.caption.as_str()).collect();
+        fs::remove_dir_all(&dir).ok();
+        assert_eq!(
+            captions,
+            [
+                "contact_sheet.jpg",
+                "recording1/frame1.TIFF",
+                "screenshot9.png",
+                "screenshot10.png",
+            ]
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use super::template::TemplateError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Everything that can go wrong while capturing or saving screenshots.
+#[derive(Debug)]
+pub enum ScreenshotError {
+    /// No window exists for the id given to `Shots::new`.
+    WindowNotFound,
+    /// An output directory could not be created.
+    CreateDir {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A sink failed to write a frame.
+    Write {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A sink failed to finish its output.
+    Sink(io::Error),
+    /// The GPU did not release a frame before the deadline, so it was dropped.
+    Timeout {
+        path: PathBuf,
+    },
+    /// Creating Vulkan resources or recording capture commands failed.
+    Vulkan {
+        context: &'static str,
+        message: String,
+    },
+    /// A poster was abandoned because one of its tiles could not be stitched.
+    Tile {
+        index: usize,
+        reason: &'static str,
+    },
+    Template(TemplateError),
+    /// A metadata key that cannot be stored as a PNG text keyword.
+    MetadataKey(String),
+    /// The saving thread is no longer running.
+    SavingThreadStopped,
+}
+
+impl ScreenshotError {
+    /// Wraps a Vulkan error with a description of what was being done.
+    pub(crate) fn vulkan<E: fmt::Debug>(context: &'static str) -> impl FnOnce(E) -> Self {
+        move |e| ScreenshotError::Vulkan {
+            context,
+            message: format!("{:?}", e),
+        }
+    }
+
+    pub(crate) fn create_dir(path: PathBuf) -> impl FnOnce(io::Error) -> Self {
+        move |source| ScreenshotError::CreateDir { path, source }
+    }
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScreenshotError::WindowNotFound => write!(f, "window not found"),
+            ScreenshotError::CreateDir { path, source } => {
+                write!(f, "failed to create {}: {}", path.display(), source)
+            }
+            ScreenshotError::Write { path, source } => {
+                write!(f, "failed to write {}: {}", path.display(), source)
+            }
+            ScreenshotError::Sink(source) => write!(f, "failed to finish output: {}", source),
+            ScreenshotError::Timeout { path } => write!(
+                f,
+                "gave up on {} while the GPU was still using it",
+                path.display()
+            ),
+            ScreenshotError::Vulkan { context, message } => write!(f, "{}: {}", context, message),
+            ScreenshotError::Tile { index, reason } => {
+                write!(f, "dropped poster at tile {}: {}", index, reason)
+            }
+            ScreenshotError::Template(e) => e.fmt(f),
+            ScreenshotError::MetadataKey(key) => {
+                write!(f, "{:?} is not a valid PNG text keyword", key)
+            }
+            ScreenshotError::SavingThreadStopped => write!(f, "the saving thread has stopped"),
+        }
+    }
+}
+
+impl Error for ScreenshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ScreenshotError::CreateDir { source, .. }
+            | ScreenshotError::Write { source, .. }
+            | ScreenshotError::Sink(source) => Some(source),
+            ScreenshotError::Template(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<TemplateError> for ScreenshotError {
+    fn from(e: TemplateError) -> Self {
+        ScreenshotError::Template(e)
+    }
+}
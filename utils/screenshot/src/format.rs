@@ -0,0 +1,523 @@
+use super::metadata::{self, Metadata};
+use super::sink::Counter;
+use nannou::image;
+use png::HasParameters;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Precision of the pixels read back from the GPU.
+///
+/// Samples are stored in native byte order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    U8,
+    U16,
+    F32,
+}
+
+/// File format written by `ImageSink`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    /// PNG with 16 bits per channel.
+    Png16,
+    /// JPEG with `quality` from 1 (worst) to 100 (best). Alpha is dropped.
+    Jpeg { quality: u8 },
+    Bmp,
+    /// Uncompressed TIFF with 8 bits per channel.
+    Tiff,
+    /// Uncompressed TIFF with 16 bits per channel.
+    Tiff16,
+    /// Uncompressed OpenEXR with linear 32 bit float channels.
+    Exr,
+}
+
+impl SampleFormat {
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            SampleFormat::U8 => 1,
+            SampleFormat::U16 => 2,
+            SampleFormat::F32 => 4,
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png | OutputFormat::Png16 => "png",
+            OutputFormat::Jpeg { .. } => "jpg",
+            OutputFormat::Bmp => "bmp",
+            OutputFormat::Tiff | OutputFormat::Tiff16 => "tiff",
+            OutputFormat::Exr => "exr",
+        }
+    }
+
+    /// The readback precision needed to fill this format.
+    pub fn sample_format(self) -> SampleFormat {
+        match self {
+            OutputFormat::Png16 | OutputFormat::Tiff16 => SampleFormat::U16,
+            OutputFormat::Exr => SampleFormat::F32,
+            _ => SampleFormat::U8,
+        }
+    }
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Png
+    }
+}
+
+/// Encodes RGBA `data` of the given sample format to `path`,
+/// converting the samples if `format` needs a different precision.
+pub fn write_image(
+    path: &Path,
+    data: &[u8],
+    dims: (usize, usize),
+    sample: SampleFormat,
+    format: OutputFormat,
+) -> io::Result<()> {
+    write_image_with_metadata(path, data, dims, sample, format, &Metadata::new())
+}
+
+/// Same as `write_image`, storing `metadata` as text chunks in PNG files.
+/// Other formats ignore it.
+pub fn write_image_with_metadata(
+    path: &Path,
+    data: &[u8],
+    dims: (usize, usize),
+    sample: SampleFormat,
+    format: OutputFormat,
+    metadata: &Metadata,
+) -> io::Result<()> {
+    write_file(path, data, dims, sample, format, metadata).map(|_| ())
+}
+
+/// Same as `write_image_with_metadata`, returning the size of the file.
+pub(crate) fn write_file(
+    path: &Path,
+    data: &[u8],
+    dims: (usize, usize),
+    sample: SampleFormat,
+    format: OutputFormat,
+    metadata: &Metadata,
+) -> io::Result<u64> {
+    let (w, h) = (dims.0 as u32, dims.1 as u32);
+    let mut out = Counter::new(BufWriter::new(File::create(path)?));
+    match format {
+        OutputFormat::Png => write_png(&mut out, &to_u8(data, sample), dims, 8, metadata),
+        OutputFormat::Png16 => {
+            // PNG stores 16 bit samples big endian
+            let bytes: Vec<u8> = to_u16(data, sample)
+                .iter()
+                .flat_map(|s| s.to_be_bytes().to_vec())
+                .collect();
+            write_png(&mut out, &bytes, dims, 16, metadata)
+        }
+        OutputFormat::Jpeg { quality } => {
+            image::jpeg::JPEGEncoder::new_with_quality(&mut out, quality.max(1).min(100))
+                .encode(&to_u8(data, sample), w, h, image::ColorType::RGBA(8))
+        }
+        OutputFormat::Bmp => image::bmp::BMPEncoder::new(&mut out).encode(
+            &to_u8(data, sample),
+            w,
+            h,
+            image::ColorType::RGBA(8),
+        ),
+        OutputFormat::Tiff => write_tiff(&mut out, &to_u8(data, sample), dims, 8),
+        OutputFormat::Tiff16 => {
+            let bytes: Vec<u8> = to_u16(data, sample)
+                .iter()
+                .flat_map(|s| s.to_le_bytes().to_vec())
+                .collect();
+            write_tiff(&mut out, &bytes, dims, 16)
+        }
+        OutputFormat::Exr => write_exr(&mut out, &to_f32(data, sample), dims),
+    }?;
+    out.flush()?;
+    Ok(out.take())
+}
+
+pub(crate) fn to_u8(data: &[u8], sample: SampleFormat) -> Cow<'_, [u8]> {
+    match sample {
+        SampleFormat::U8 => Cow::Borrowed(data),
+        SampleFormat::U16 => Cow::Owned(
+            data.chunks_exact(2)
+                .map(|b| {
+                    let s = u16::from_ne_bytes([b[0], b[1]]) as u32;
+                    ((s * 255 + 32767) / 65535) as u8
+                })
+                .collect(),
+        ),
+        SampleFormat::F32 => Cow::Owned(
+            data.chunks_exact(4)
+                .map(|b| {
+                    let s = f32::from_ne_bytes([b[0], b[1], b[2], b[3]]);
+                    (s.max(0.0).min(1.0) * 255.0).round() as u8
+                })
+                .collect(),
+        ),
+    }
+}
+
+pub(crate) fn to_u16(data: &[u8], sample: SampleFormat) -> Vec<u16> {
+    match sample {
+        SampleFormat::U8 => data.iter().map(|&s| s as u16 * 257).collect(),
+        SampleFormat::U16 => data
+            .chunks_exact(2)
+            .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+            .collect(),
+        SampleFormat::F32 => data
+            .chunks_exact(4)
+            .map(|b| {
+                let s = f32::from_ne_bytes([b[0], b[1], b[2], b[3]]);
+                (s.max(0.0).min(1.0) * 65535.0).round() as u16
+            })
+            .collect(),
+    }
+}
+
+pub(crate) fn to_f32(data: &[u8], sample: SampleFormat) -> Vec<f32> {
+    match sample {
+        SampleFormat::U8 => data.iter().map(|&s| s as f32 / 255.0).collect(),
+        SampleFormat::U16 => data
+            .chunks_exact(2)
+            .map(|b| u16::from_ne_bytes([b[0], b[1]]) as f32 / 65535.0)
+            .collect(),
+        SampleFormat::F32 => data
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+    }
+}
+
+/// Encodes 8 bit RGBA `data` as a PNG in memory.
+pub(crate) fn encode_png(
+    data: &[u8],
+    dims: (usize, usize),
+    metadata: &Metadata,
+) -> io::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    write_png(&mut png, data, dims, 8, metadata)?;
+    Ok(png)
+}
+
+fn write_png<W: Write>(
+    w: &mut W,
+    data: &[u8],
+    dims: (usize, usize),
+    bits: u8,
+    metadata: &Metadata,
+) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(&mut *w, dims.0 as u32, dims.1 as u32);
+    let depth = match bits {
+        16 => png::BitDepth::Sixteen,
+        _ => png::BitDepth::Eight,
+    };
+    encoder.set(png::ColorType::RGBA).set(depth);
+    let mut writer = encoder.write_header()?;
+    for (name, chunk) in metadata::png_chunks(metadata)? {
+        writer.write_chunk(name, &chunk)?;
+    }
+    writer.write_image_data(data)?;
+    // IEND is written when the writer is dropped
+    drop(writer);
+    w.flush()
+}
+
+/// Baseline little endian TIFF with a single uncompressed RGBA strip.
+/// `data` holds little endian samples of `bits` 8 or 16.
+fn write_tiff<W: Write>(
+    w: &mut W,
+    data: &[u8],
+    dims: (usize, usize),
+    bits: u16,
+) -> io::Result<()> {
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+    const NUM_ENTRIES: u32 = 11;
+    let ifd_offset = 8;
+    let bits_offset = ifd_offset + 2 + NUM_ENTRIES * 12 + 4;
+    let data_offset = bits_offset + 8;
+    let (width, height) = (dims.0 as u32, dims.1 as u32);
+    let entries: [(u16, u16, u32, u32); NUM_ENTRIES as usize] = [
+        (256, LONG, 1, width),
+        (257, LONG, 1, height),
+        (258, SHORT, 4, bits_offset),
+        // No compression
+        (259, SHORT, 1, 1),
+        // RGB photometric interpretation
+        (262, SHORT, 1, 2),
+        (273, LONG, 1, data_offset),
+        (277, SHORT, 1, 4),
+        (278, LONG, 1, height),
+        (279, LONG, 1, data.len() as u32),
+        // Chunky planar configuration
+        (284, SHORT, 1, 1),
+        // Unassociated alpha
+        (338, SHORT, 1, 2),
+    ];
+    w.write_all(b"II")?;
+    w.write_all(&42u16.to_le_bytes())?;
+    w.write_all(&ifd_offset.to_le_bytes())?;
+    w.write_all(&(NUM_ENTRIES as u16).to_le_bytes())?;
+    for &(tag, ty, count, value) in entries.iter() {
+        w.write_all(&tag.to_le_bytes())?;
+        w.write_all(&ty.to_le_bytes())?;
+        w.write_all(&count.to_le_bytes())?;
+        if ty == SHORT && count == 1 {
+            w.write_all(&(value as u16).to_le_bytes())?;
+            w.write_all(&[0, 0])?;
+        } else {
+            w.write_all(&value.to_le_bytes())?;
+        }
+    }
+    // No further IFDs
+    w.write_all(&0u32.to_le_bytes())?;
+    for _ in 0..4 {
+        w.write_all(&bits.to_le_bytes())?;
+    }
+    w.write_all(data)?;
+    w.flush()
+}
+
+/// Single part scanline OpenEXR with uncompressed float RGBA channels.
+fn write_exr<W: Write>(w: &mut W, pixels: &[f32], dims: (usize, usize)) -> io::Result<()> {
+    fn attribute(header: &mut Vec<u8>, name: &str, ty: &str, value: &[u8]) {
+        header.extend_from_slice(name.as_bytes());
+        header.push(0);
+        header.extend_from_slice(ty.as_bytes());
+        header.push(0);
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    }
+    if dims.0 == 0 || dims.1 == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "EXR images need at least one pixel",
+        ));
+    }
+    const FLOAT: i32 = 2;
+    // Channels must be listed alphabetically
+    const CHANNELS: [(&str, usize); 4] = [("A", 3), ("B", 2), ("G", 1), ("R", 0)];
+    let (width, height) = dims;
+
+    let mut channels = vec![];
+    for &(name, _) in CHANNELS.iter() {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&FLOAT.to_le_bytes());
+        // pLinear and reserved bytes
+        channels.extend_from_slice(&[0; 4]);
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+    let mut window = vec![];
+    for &v in [0, 0, width as i32 - 1, height as i32 - 1].iter() {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+
+    let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    attribute(&mut header, "channels", "chlist", &channels);
+    attribute(&mut header, "compression", "compression", &[0]);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+    w.write_all(&header)?;
+
+    let line_size = width * CHANNELS.len() * 4;
+    let first_line = header.len() + height * 8;
+    for y in 0..height {
+        let offset = first_line + y * (8 + line_size);
+        w.write_all(&(offset as u64).to_le_bytes())?;
+    }
+    let mut line = Vec::with_capacity(line_size);
+    for (y, row) in pixels.chunks_exact(width * 4).enumerate() {
+        line.clear();
+        for &(_, c) in CHANNELS.iter() {
+            for pixel in row.chunks_exact(4) {
+                line.extend_from_slice(&pixel[c].to_le_bytes());
+            }
+        }
+        w.write_all(&(y as i32).to_le_bytes())?;
+        w.write_all(&(line_size as i32).to_le_bytes())?;
+        w.write_all(&line)?;
+    }
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        let mut b = [0; 4];
+        b.copy_from_slice(&bytes[offset..offset + 4]);
+        u32::from_le_bytes(b)
+    }
+
+    /// The value of the IFD entry with `tag`.
+    fn tiff_entry(tiff: &[u8], tag: u16) -> u32 {
+        let count = u16_at(tiff, 8) as usize;
+        let entry = (0..count)
+            .map(|i| 10 + i * 12)
+            .find(|&entry| u16_at(tiff, entry) == tag)
+            .expect("missing TIFF tag");
+        match u16_at(tiff, entry + 2) {
+            3 if u32_at(tiff, entry + 4) == 1 => u16_at(tiff, entry + 8) as u32,
+            _ => u32_at(tiff, entry + 8),
+        }
+    }
+
+    fn ne_bytes_u16(samples: &[u16]) -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|s| s.to_ne_bytes().to_vec())
+            .collect()
+    }
+
+    fn ne_bytes_f32(samples: &[f32]) -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|s| s.to_ne_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn tiff_header_and_strip() {
+        let data: Vec<u8> = (0..16).collect();
+        let mut tiff = vec![];
+        write_tiff(&mut tiff, &data, (2, 2), 8).unwrap();
+        assert_eq!(&tiff[..4], b"II\x2a\x00");
+        assert_eq!(u32_at(&tiff, 4), 8);
+        assert_eq!(tiff_entry(&tiff, 256), 2);
+        assert_eq!(tiff_entry(&tiff, 257), 2);
+        assert_eq!(tiff_entry(&tiff, 259), 1);
+        assert_eq!(tiff_entry(&tiff, 277), 4);
+        assert_eq!(tiff_entry(&tiff, 279), 16);
+        let bits = tiff_entry(&tiff, 258) as usize;
+        for i in 0..4 {
+            assert_eq!(u16_at(&tiff, bits + i * 2), 8);
+        }
+        let strip = tiff_entry(&tiff, 273) as usize;
+        assert_eq!(&tiff[strip..], &data[..]);
+    }
+
+    #[test]
+    fn tiff16_samples_are_little_endian() {
+        let path =
+            std::env::temp_dir().join(format!("screenshot-format-{}.tiff", std::process::id()));
+        let samples = [0x0102, 0x0304, 0x0506, 0xffff];
+        let bytes = write_file(
+            &path,
+            &ne_bytes_u16(&samples),
+            (1, 1),
+            SampleFormat::U16,
+            OutputFormat::Tiff16,
+            &Metadata::new(),
+        )
+        .unwrap();
+        let tiff = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(bytes, tiff.len() as u64);
+        let bits = tiff_entry(&tiff, 258) as usize;
+        assert_eq!(u16_at(&tiff, bits), 16);
+        let strip = tiff_entry(&tiff, 273) as usize;
+        assert_eq!(&tiff[strip..], &[2, 1, 4, 3, 6, 5, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn png16_samples_are_big_endian() {
+        let samples = [0x0102, 0x0304, 0x0506, 0xffff];
+        let mut png = vec![];
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|s: &u16| s.to_be_bytes().to_vec())
+            .collect();
+        write_png(&mut png, &bytes, (1, 1), 16, &Metadata::new()).unwrap();
+        let mut decoder = png::Decoder::new(&png[..]);
+        decoder.set(png::Transformations::IDENTITY);
+        let (info, mut reader) = decoder.read_info().unwrap();
+        assert_eq!(info.bit_depth, png::BitDepth::Sixteen);
+        let mut decoded = vec![0; info.buffer_size()];
+        reader.next_frame(&mut decoded).unwrap();
+        assert_eq!(decoded, [1, 2, 3, 4, 5, 6, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn exr_header_and_scanlines() {
+        // Two pixels in one row: R, G, B, A each
+        let pixels = [0.1, 0.2, 0.3, 1.0, 0.5, 0.6, 0.7, 0.0];
+        let mut exr = vec![];
+        write_exr(&mut exr, &pixels, (2, 1)).unwrap();
+        assert_eq!(&exr[..4], &[0x76, 0x2f, 0x31, 0x01]);
+        assert_eq!(u32_at(&exr, 4), 2);
+        for attribute in &[
+            &b"channels\0chlist\0"[..],
+            b"compression\0compression\0",
+            b"dataWindow\0box2i\0",
+            b"lineOrder\0lineOrder\0",
+        ] {
+            assert!(exr.windows(attribute.len()).any(|w| w == *attribute));
+        }
+        // One scanline: offset table, then y, size and the channels A, B, G, R
+        let line_size = 2 * 4 * 4;
+        let line = exr.len() - line_size - 8;
+        assert_eq!(u32_at(&exr, line - 8) as usize, line);
+        assert_eq!(u32_at(&exr, line), 0);
+        assert_eq!(u32_at(&exr, line + 4) as usize, line_size);
+        let values: Vec<f32> = exr[line + 8..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(values, [1.0, 0.0, 0.3, 0.7, 0.2, 0.6, 0.1, 0.5]);
+    }
+
+    #[test]
+    fn empty_exr_is_an_error() {
+        let mut exr = vec![];
+        assert!(write_exr(&mut exr, &[], (0, 4)).is_err());
+        assert!(write_exr(&mut exr, &[], (4, 0)).is_err());
+        assert!(exr.is_empty());
+    }
+
+    #[test]
+    fn u8_samples_round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        let wide = ne_bytes_u16(&to_u16(&data, SampleFormat::U8));
+        assert_eq!(to_u8(&wide, SampleFormat::U16), &data[..]);
+        let float = ne_bytes_f32(&to_f32(&data, SampleFormat::U8));
+        assert_eq!(to_u8(&float, SampleFormat::F32), &data[..]);
+        assert_eq!(to_u16(&data[255..], SampleFormat::U8), [65535]);
+    }
+
+    #[test]
+    fn u16_samples_round_trip_through_f32() {
+        let samples: Vec<u16> = (0..=65535)
+            .step_by(257)
+            .chain(vec![1, 1000, 65534])
+            .collect();
+        let float = ne_bytes_f32(&to_f32(&ne_bytes_u16(&samples), SampleFormat::U16));
+        assert_eq!(to_u16(&float, SampleFormat::F32), samples);
+    }
+
+    #[test]
+    fn float_samples_are_clamped() {
+        let data = ne_bytes_f32(&[-0.5, 0.0, 0.5, 1.5]);
+        assert_eq!(to_u8(&data, SampleFormat::F32), &[0, 0, 128, 255][..]);
+        assert_eq!(to_u16(&data, SampleFormat::F32), [0, 0, 32768, 65535]);
+        assert_eq!(to_f32(&data, SampleFormat::F32), [-0.5, 0.0, 0.5, 1.5]);
+    }
+}
@@ -0,0 +1,500 @@
+use super::format::{self, OutputFormat, SampleFormat};
+use nannou::image::{self, RgbaImage};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Setting this variable to anything but `0` turns on `Golden::bless`.
+pub const BLESS_VAR: &str = "SCREENSHOT_BLESS";
+
+/// Side of the square windows SSIM is averaged over, and their step.
+const SSIM_WINDOW: usize = 8;
+const SSIM_STEP: usize = 4;
+/// Differences are multiplied by this in diff images, so small ones show.
+const DIFF_GAIN: u32 = 8;
+
+/// How far an image may stray from its reference before the check fails.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    /// Largest difference allowed in any channel of any pixel.
+    pub max_diff: u8,
+    /// Lowest peak signal to noise ratio allowed, in dB.
+    pub min_psnr: f64,
+    /// Lowest structural similarity allowed, 1.0 meaning identical.
+    pub min_ssim: f64,
+}
+
+/// How an image differs from its reference.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Comparison {
+    /// Largest difference in red, green, blue and alpha.
+    pub max_diff: [u8; 4],
+    /// Peak signal to noise ratio over all channels in dB, infinite for identical images.
+    pub psnr: f64,
+    /// Mean structural similarity of the luma.
+    pub ssim: f64,
+}
+
+/// Compares images against references stored in a directory,
+/// to catch visual regressions in sketches.
+///
+/// A failed check writes `{name}.actual.png` and `{name}.diff.png` next to
+/// the reference. In bless mode the references are replaced instead, so
+/// run once with `SCREENSHOT_BLESS=1` after an intended change.
+///
+/// ```no_run
+/// # use nannou::image::RgbaImage;
+/// # use screenshot::{Golden, GoldenError, Shots};
+/// # use std::sync::mpsc::Receiver;
+/// # struct Model { pending: Option<Receiver<RgbaImage>> }
+/// # fn check(shots: &Shots, model: &mut Model) -> Result<(), GoldenError> {
+/// let golden = Golden::new(concat!(env!("CARGO_MANIFEST_DIR"), "/golden"));
+/// model.pending = Some(shots.capture_to_memory());
+/// // ... in `update` of a later frame, so the render loop never waits
+/// if let Some(Ok(image)) = model.pending.as_ref().map(|rx| rx.try_recv()) {
+///     model.pending = None;
+///     golden.check("spiral", &image)?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Golden {
+    dir: PathBuf,
+    tolerance: Tolerance,
+    bless: bool,
+}
+
+/// Why a check against a reference failed.
+#[derive(Debug)]
+pub enum GoldenError {
+    /// There is no reference yet. Run in bless mode to store the image as one.
+    Missing { reference: PathBuf },
+    /// The image and its reference differ in size.
+    Size {
+        reference: PathBuf,
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    /// The image is outside the tolerance. `diff` shows where.
+    Mismatch {
+        reference: PathBuf,
+        diff: PathBuf,
+        comparison: Comparison,
+        tolerance: Tolerance,
+    },
+    /// Reading the reference or writing an image failed.
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl Default for Tolerance {
+    /// Lets through the rounding differences between GPUs and drivers, little else.
+    fn default() -> Self {
+        Tolerance {
+            max_diff: 4,
+            min_psnr: 40.0,
+            min_ssim: 0.99,
+        }
+    }
+}
+
+impl Tolerance {
+    /// Only identical images pass.
+    pub fn exact() -> Self {
+        Tolerance {
+            max_diff: 0,
+            min_psnr: std::f64::INFINITY,
+            min_ssim: 1.0,
+        }
+    }
+}
+
+impl Comparison {
+    /// Measures how `actual` differs from `expected`, which must be the same size.
+    pub fn new(expected: &RgbaImage, actual: &RgbaImage) -> Self {
+        let mut max_diff = [0u8; 4];
+        let mut squared = 0u64;
+        for (e, a) in expected.chunks_exact(4).zip(actual.chunks_exact(4)) {
+            for (c, (&e, &a)) in e.iter().zip(a).enumerate() {
+                let diff = (e as i32 - a as i32).abs() as u8;
+                max_diff[c] = max_diff[c].max(diff);
+                squared += diff as u64 * diff as u64;
+            }
+        }
+        let samples = (expected.len() as f64).max(1.0);
+        let mse = squared as f64 / samples;
+        let psnr = if mse == 0.0 {
+            std::f64::INFINITY
+        } else {
+            10.0 * (255.0 * 255.0 / mse).log10()
+        };
+        let dims = (expected.width() as usize, expected.height() as usize);
+        let ssim = ssim(&luma(expected), &luma(actual), dims);
+        Comparison {
+            max_diff,
+            psnr,
+            ssim,
+        }
+    }
+
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        self.max_diff.iter().all(|&d| d <= tolerance.max_diff)
+            && self.psnr >= tolerance.min_psnr
+            && self.ssim >= tolerance.min_ssim
+    }
+}
+
+impl Golden {
+    /// Reads and writes references in `dir`, with the default tolerance.
+    /// Bless mode is on when `SCREENSHOT_BLESS` is set.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        let bless = match env::var(BLESS_VAR) {
+            Ok(value) => !value.is_empty() && value != "0",
+            Err(_) => false,
+        };
+        Golden {
+            dir: dir.as_ref().to_path_buf(),
+            tolerance: Tolerance::default(),
+            bless,
+        }
+    }
+
+    pub fn tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// In bless mode every check stores its image as the new reference and passes.
+    pub fn bless(mut self, bless: bool) -> Self {
+        self.bless = bless;
+        self
+    }
+
+    /// Where the reference called `name` is stored.
+    pub fn reference(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.png", name))
+    }
+
+    /// Compares `image` with the reference called `name`.
+    pub fn check(&self, name: &str, image: &RgbaImage) -> Result<Comparison, GoldenError> {
+        let reference = self.reference(name);
+        let actual_path = self.dir.join(format!("{}.actual.png", name));
+        let diff_path = self.dir.join(format!("{}.diff.png", name));
+        if self.bless {
+            fs::create_dir_all(&self.dir).map_err(GoldenError::io(&self.dir))?;
+            write_png(&reference, image)?;
+            remove_stale(&[&actual_path, &diff_path]);
+            return Ok(Comparison::new(image, image));
+        }
+        if !reference.exists() {
+            return Err(GoldenError::Missing { reference });
+        }
+        let expected = image::open(&reference)
+            .map_err(|e| GoldenError::Io {
+                path: reference.clone(),
+                source: io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+            })?
+            .to_rgba();
+        if expected.dimensions() != image.dimensions() {
+            write_png(&actual_path, image)?;
+            return Err(GoldenError::Size {
+                reference,
+                expected: expected.dimensions(),
+                actual: image.dimensions(),
+            });
+        }
+        let comparison = Comparison::new(&expected, image);
+        if comparison.passes(&self.tolerance) {
+            remove_stale(&[&actual_path, &diff_path]);
+            return Ok(comparison);
+        }
+        write_png(&actual_path, image)?;
+        write_png(&diff_path, &diff_image(&expected, image))?;
+        Err(GoldenError::Mismatch {
+            reference,
+            diff: diff_path,
+            comparison,
+            tolerance: self.tolerance,
+        })
+    }
+
+    /// Same as `Golden::check` for an image saved to `path`, such as a screenshot.
+    pub fn check_file(&self, name: &str, path: &Path) -> Result<Comparison, GoldenError> {
+        let image = image::open(path)
+            .map_err(|e| GoldenError::Io {
+                path: path.to_path_buf(),
+                source: io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+            })?
+            .to_rgba();
+        self.check(name, &image)
+    }
+}
+
+impl GoldenError {
+    fn io(path: &Path) -> impl FnOnce(io::Error) -> Self {
+        let path = path.to_path_buf();
+        move |source| GoldenError::Io { path, source }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [r, g, b, a] = self.max_diff;
+        write!(
+            f,
+            "max diff r {} g {} b {} a {}, PSNR {:.2} dB, SSIM {:.4}",
+            r, g, b, a, self.psnr, self.ssim
+        )
+    }
+}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GoldenError::Missing { reference } => write!(
+                f,
+                "no reference at {}, set {}=1 to create it",
+                reference.display(),
+                BLESS_VAR
+            ),
+            GoldenError::Size {
+                reference,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} is {}x{} but the image is {}x{}",
+                reference.display(),
+                expected.0,
+                expected.1,
+                actual.0,
+                actual.1
+            ),
+            GoldenError::Mismatch {
+                reference,
+                diff,
+                comparison,
+                tolerance,
+            } => write!(
+                f,
+                "image differs from {}: {} (allowed max diff {}, PSNR {:.2} dB, SSIM {:.4}), see {}",
+                reference.display(),
+                comparison,
+                tolerance.max_diff,
+                tolerance.min_psnr,
+                tolerance.min_ssim,
+                diff.display()
+            ),
+            GoldenError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl Error for GoldenError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GoldenError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+fn write_png(path: &Path, image: &RgbaImage) -> Result<(), GoldenError> {
+    let dims = (image.width() as usize, image.height() as usize);
+    format::write_image(path, image, dims, SampleFormat::U8, OutputFormat::Png)
+        .map_err(GoldenError::io(path))
+}
+
+/// Leftovers of an earlier failure would be mistaken for a current one.
+fn remove_stale(paths: &[&Path]) {
+    for path in paths {
+        fs::remove_file(path).ok();
+    }
+}
+
+/// Rec. 709 luma of every pixel, ignoring alpha.
+fn luma(image: &RgbaImage) -> Vec<f64> {
+    image
+        .chunks_exact(4)
+        .map(|p| 0.2126 * p[0] as f64 + 0.7152 * p[1] as f64 + 0.0722 * p[2] as f64)
+        .collect()
+}
+
+/// Mean SSIM over overlapping square windows. Images smaller
+/// than a window are compared as one window.
+fn ssim(x: &[f64], y: &[f64], dims: (usize, usize)) -> f64 {
+    let window = (SSIM_WINDOW.min(dims.0), SSIM_WINDOW.min(dims.1));
+    if window.0 == 0 || window.1 == 0 {
+        return 1.0;
+    }
+    let starts = |len: usize, size: usize| {
+        let mut starts: Vec<usize> = (0..=len - size).step_by(SSIM_STEP).collect();
+        // The last row and column are covered too
+        if starts.last() != Some(&(len - size)) {
+            starts.push(len - size);
+        }
+        starts
+    };
+    let (xs, ys) = (starts(dims.0, window.0), starts(dims.1, window.1));
+    let mut total = 0.0;
+    for &top in &ys {
+        for &left in &xs {
+            total += ssim_window(x, y, dims.0, (left, top), window);
+        }
+    }
+    total / (xs.len() * ys.len()) as f64
+}
+
+fn ssim_window(
+    x: &[f64],
+    y: &[f64],
+    stride: usize,
+    at: (usize, usize),
+    size: (usize, usize),
+) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let n = (size.0 * size.1) as f64;
+    let (mut sx, mut sy, mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for row in at.1..at.1 + size.1 {
+        let start = row * stride + at.0;
+        for (&a, &b) in x[start..start + size.0]
+            .iter()
+            .zip(&y[start..start + size.0])
+        {
+            sx += a;
+            sy += b;
+            sxx += a * a;
+            syy += b * b;
+            sxy += a * b;
+        }
+    }
+    let (mx, my) = (sx / n, sy / n);
+    let vx = sxx / n - mx * mx;
+    let vy = syy / n - my * my;
+    let cov = sxy / n - mx * my;
+    ((2.0 * mx * my + C1) * (2.0 * cov + C2)) / ((mx * mx + my * my + C1) * (vx + vy + C2))
+}
+
+/// The reference dimmed to grey, with differing pixels in red
+/// as bright as their largest channel difference.
+fn diff_image(expected: &RgbaImage, actual: &RgbaImage) -> RgbaImage {
+    let mut diff = RgbaImage::new(expected.width(), expected.height());
+    for (out, (e, a)) in diff
+        .pixels_mut()
+        .zip(expected.pixels().zip(actual.pixels()))
+    {
+        let largest = e
+            .data
+            .iter()
+            .zip(a.data.iter())
+            .map(|(&e, &a)| (e as i32 - a as i32).abs() as u32)
+            .max()
+            .unwrap_or(0);
+        let [r, g, b, _] = e.data;
+        let grey = ((r as u32 * 2 + g as u32 * 7 + b as u32) / 10 / 4) as u8;
+        out.data = if largest == 0 {
+            [grey, grey, grey, 255]
+        } else {
+            [
+                (largest * DIFF_GAIN).max(64).min(255) as u8,
+                grey / 2,
+                grey / 2,
+                255,
+            ]
+        };
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn gradient() -> RgbaImage {
+        RgbaImage::from_fn(16, 16, |x, y| {
+            image::Rgba([(x * 8) as u8, (y * 8) as u8, 128, 255])
+        })
+    }
+
+    /// `image` with `offset` added to the red channel of every pixel.
+    fn offset(image: &RgbaImage, offset: u8) -> RgbaImage {
+        let mut offset_image = image.clone();
+        for pixel in offset_image.pixels_mut() {
+            pixel.data[0] += offset;
+        }
+        offset_image
+    }
+
+    /// An empty directory of its own for each test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("screenshot-golden-{}-{}", process::id(), name));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    #[test]
+    fn identical_images() {
+        let image = gradient();
+        let comparison = Comparison::new(&image, &image);
+        assert_eq!(comparison.max_diff, [0; 4]);
+        assert!(comparison.psnr.is_infinite());
+        assert_eq!(comparison.ssim, 1.0);
+        assert!(comparison.passes(&Tolerance::exact()));
+    }
+
+    #[test]
+    fn known_offset() {
+        let expected = gradient();
+        let comparison = Comparison::new(&expected, &offset(&expected, 10));
+        assert_eq!(comparison.max_diff, [10, 0, 0, 0]);
+        // One channel in four is off by 10, so the MSE is 25
+        let psnr = 10.0 * (255.0f64 * 255.0 / 25.0).log10();
+        assert!((comparison.psnr - psnr).abs() < 1e-9);
+        assert!(comparison.ssim < 1.0);
+        assert!(!comparison.passes(&Tolerance::default()));
+        let loose = Tolerance {
+            max_diff: 10,
+            min_psnr: 30.0,
+            min_ssim: 0.9,
+        };
+        assert!(comparison.passes(&loose));
+    }
+
+    #[test]
+    fn mismatch_writes_diff_until_blessed() {
+        let dir = temp_dir("bless");
+        let golden = Golden::new(&dir).bless(false);
+        let expected = gradient();
+        let actual = offset(&expected, 40);
+        match golden.check("gradient", &expected) {
+            Err(GoldenError::Missing { .. }) => {}
+            other => panic!("expected a missing reference, got {:?}", other),
+        }
+
+        let blessing = Golden::new(&dir).bless(true);
+        blessing.check("gradient", &expected).unwrap();
+        assert!(golden.reference("gradient").exists());
+        golden.check("gradient", &expected).unwrap();
+
+        let diff = match golden.check("gradient", &actual) {
+            Err(GoldenError::Mismatch { diff, .. }) => diff,
+            other => panic!("expected a mismatch, got {:?}", other),
+        };
+        assert!(dir.join("gradient.actual.png").exists());
+        let diff = image::open(&diff).unwrap().to_rgba();
+        assert_eq!(diff.dimensions(), expected.dimensions());
+        // Every pixel differs by 40, shown in red with the gain applied
+        assert!(diff.pixels().all(|p| p.data[0] == 255 && p.data[3] == 255));
+
+        blessing.check("gradient", &actual).unwrap();
+        assert!(!dir.join("gradient.actual.png").exists());
+        assert!(!dir.join("gradient.diff.png").exists());
+        let exact = golden.tolerance(Tolerance::exact());
+        exact.check("gradient", &actual).unwrap();
+        fs::remove_dir_all(&dir).ok();
+    }
+}
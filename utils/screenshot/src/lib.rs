@@ -1,15 +1,59 @@
+use nannou::image::RgbaImage;
 use nannou::prelude::*;
+use std::borrow::Cow;
 use std::cell::Cell;
 use std::cell::RefCell;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::mem;
 use std::path::{Path, PathBuf};
 use std::slice;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
+mod alpha;
+mod animation;
 mod capture;
+mod color;
+mod contact;
+mod error;
+mod format;
+mod golden;
+mod metadata;
+mod multi;
+mod offscreen;
+mod ora;
+mod output;
+mod pool;
+mod region;
+mod replay;
+mod sink;
+mod stats;
+mod template;
+mod tile;
+mod timelapse;
+mod trigger;
+mod y4m;
+
+pub use alpha::AlphaMode;
+pub use animation::{ApngSink, Dither, GifSink, Loop};
+pub use color::{Transfer, TransferStage};
+pub use contact::{Caption, ContactSheet};
+pub use error::ScreenshotError;
+pub use format::{write_image, write_image_with_metadata, OutputFormat, SampleFormat};
+pub use golden::{Comparison, Golden, GoldenError, Tolerance, BLESS_VAR};
+pub use metadata::Metadata;
+pub use multi::MultiShots;
+pub use output::OutputPolicy;
+pub use region::{Filter, Region, Thumbnail};
+pub use sink::{FrameInfo, FrameSink, ImageSink, PngSink};
+pub use stats::Stats;
+pub use template::{FilenameTemplate, TemplateError, DEFAULT_TEMPLATE};
+pub use tile::Tile;
+pub use timelapse::Interval;
+pub use y4m::{Chroma, Y4mSink};
 
 // This must match the number of colours per
 // pixel.
@@ -18,33 +62,218 @@ mod capture;
 // RG = 2 etc.
 pub const NUM_COLOURS: usize = 4;
 
+/// Number of screenshot buffers allocated up front.
+const INITIAL_BUFFERS: usize = 3;
+
+/// Default limit the buffer pool may grow to while recording.
+pub const DEFAULT_MAX_BUFFERS: usize = 8;
+
+/// How long the saving thread waits for the GPU to release
+/// a buffer outside of `Shots::flush` before giving up on it.
+const GPU_WAIT: Duration = Duration::from_secs(1);
+
+/// How often a buffer still in use by the GPU is polled.
+const GPU_POLL: Duration = Duration::from_millis(1);
+
 #[derive(Clone)]
 pub(crate) struct Buffer {
-    buffer: Arc<vk::CpuAccessibleBuffer<[[u8; NUM_COLOURS]]>>,
+    buffer: PixelBuffer,
     dims: (usize, usize),
 }
 
+// The element type must match the format of the
+// output image it is copied from
+#[derive(Clone)]
+pub(crate) enum PixelBuffer {
+    U8(Arc<vk::CpuAccessibleBuffer<[[u8; NUM_COLOURS]]>>),
+    U16(Arc<vk::CpuAccessibleBuffer<[[u16; NUM_COLOURS]]>>),
+    F32(Arc<vk::CpuAccessibleBuffer<[[f32; NUM_COLOURS]]>>),
+}
+
+/// Pixel storage that can be handed to the saving thread.
+pub(crate) trait ImageData: Send + 'static {
+    fn dims(&self) -> (usize, usize);
+
+    fn sample_format(&self) -> SampleFormat;
+
+    /// Calls `f` with the RGBA bytes of the image.
+    /// Returns `None` if the image could not be read.
+    fn read<R, F: FnOnce(&[u8]) -> R>(&self, f: F) -> Option<R>;
+
+    /// Same as `read`, polling while the GPU still holds the image.
+    /// Returns `None` if it was not released by `deadline`.
+    fn read_by<R, F: FnOnce(&[u8]) -> R>(&self, deadline: Instant, f: F) -> Option<R> {
+        let mut f = Some(f);
+        loop {
+            let read = self.read(|data| f.take().map(|f| f(data)));
+            match read.and_then(|r| r) {
+                Some(r) => return Some(r),
+                None if Instant::now() >= deadline => return None,
+                None => thread::sleep(GPU_POLL),
+            }
+        }
+    }
+}
+
+impl PixelBuffer {
+    fn sample_format(&self) -> SampleFormat {
+        match self {
+            PixelBuffer::U8(_) => SampleFormat::U8,
+            PixelBuffer::U16(_) => SampleFormat::U16,
+            PixelBuffer::F32(_) => SampleFormat::F32,
+        }
+    }
+}
+
+impl ImageData for Buffer {
+    fn dims(&self) -> (usize, usize) {
+        self.dims
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        self.buffer.sample_format()
+    }
+
+    fn read<R, F: FnOnce(&[u8]) -> R>(&self, f: F) -> Option<R> {
+        fn bytes<T>(pixels: &[T]) -> &[u8] {
+            unsafe { slice::from_raw_parts(pixels.as_ptr() as *const u8, mem::size_of_val(pixels)) }
+        }
+        match &self.buffer {
+            PixelBuffer::U8(buffer) => buffer.read().ok().map(|b| f(bytes(&b))),
+            PixelBuffer::U16(buffer) => buffer.read().ok().map(|b| f(bytes(&b))),
+            PixelBuffer::F32(buffer) => buffer.read().ok().map(|b| f(bytes(&b))),
+        }
+    }
+}
+
 struct ShotWriter {
     num_images: usize,
     output_dir: PathBuf,
+    sink: Box<dyn FrameSink>,
+    template: FilenameTemplate,
+    vars: HashMap<String, String>,
+    policy: OutputPolicy,
+    metadata: Metadata,
+    sidecar: bool,
+    cpu_transfer: Option<Transfer>,
+    /// Transfer that float samples reaching the sink are encoded with.
+    float_transfer: Transfer,
+    alpha: AlphaMode,
+    region: Option<Region>,
+    thumbnail: Option<Thumbnail>,
+    replay: Option<replay::Replay>,
+    /// Encoding threads to use when the sink can be copied.
+    threads: usize,
+    /// Jobs in flight on the encoding threads, frames tagged with whether they get a sidecar.
+    pool: Option<pool::Pool<bool>>,
+    errors: Sender<ScreenshotError>,
+    acks: Sender<Ack>,
+}
+
+/// Receives a frame captured with `Shots::capture_to_memory_with`.
+type MemoryCallback = Box<dyn FnOnce(RgbaImage) + Send>;
+
+/// Receives the counters passed on by `Shots::log_stats`.
+type StatsCallback = Box<dyn FnMut(&Stats) + Send>;
+
+/// What is known about a frame when it is captured.
+struct Shot {
+    time: SystemTime,
+    metadata: Metadata,
+    /// Hands the frame to the sketch instead of the sink.
+    memory: Option<MemoryCallback>,
+    /// Part of a recording, so it also goes to the sink.
+    recorded: bool,
+}
+
+impl Shot {
+    /// A shot captured now that only goes to the sink.
+    fn new(metadata: Metadata) -> Self {
+        Shot {
+            time: SystemTime::now(),
+            metadata,
+            memory: None,
+            recorded: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Recording {
+    Off,
+    On,
+    Frames(usize),
 }
 
 // Hack to get around wait issue
 pub struct Shots {
     num_shots: Cell<usize>,
+    shot_requests: RefCell<VecDeque<(Metadata, Option<MemoryCallback>)>>,
+    recording: Cell<Recording>,
+    tile: Cell<Option<Tile>>,
+    layered_shots: Cell<usize>,
+    timelapse: Cell<Option<timelapse::Timelapse>>,
+    trigger: Cell<trigger::Trigger>,
+    replay: Cell<bool>,
+    sequence_ending: Cell<bool>,
+    num_buffers: Cell<usize>,
+    max_buffers: usize,
+    capture_policy: CapturePolicy,
+    stats: Cell<Stats>,
+    stats_log: RefCell<Option<(Duration, Instant, StatsCallback)>>,
     frames_since_empty: Cell<usize>,
     images_in: Receiver<Buffer>,
-    images_out: Sender<Msg>,
+    images_out: Sender<Envelope<Buffer>>,
+    /// Address of this window's output on the saving thread.
+    id: usize,
+    acks: Receiver<Ack>,
+    errors: Receiver<ScreenshotError>,
+    pending: Cell<usize>,
     saving_thread: Option<JoinHandle<()>>,
     frame_capture: RefCell<capture::FrameCapture>,
+    /// Assembles and reads back `Shots::capture_offscreen` targets.
+    offscreen: RefCell<Option<capture::FrameCapture>>,
+    /// Reads layers back as linear floats for `Shots::capture_layers`.
+    layer_capture: RefCell<Option<capture::FrameCapture>>,
     basedir: String,
+    root: PathBuf,
+    subdir: String,
+    policy: OutputPolicy,
+    sample: SampleFormat,
+    format_sample: SampleFormat,
+    transfer: Option<Transfer>,
+    transfer_stage: TransferStage,
+    alpha: AlphaMode,
 }
 
-enum Msg {
-    Buffer(Buffer),
+enum Msg<B> {
+    Buffer(B, Shot),
+    Tile(B, Tile, Shot),
+    /// Named layers of one frame, bottom first, in linear float buffers of their
+    /// own, with the transfer function to encode them with.
+    Layers(Vec<(String, B)>, Transfer, Shot),
+    /// A `Shots::capture_offscreen` target, in a buffer of its own.
+    Offscreen(B, Shot),
+    /// A frame only kept for the instant replay.
+    Replay(B, SystemTime),
+    ReplayLength(Option<Duration>),
+    SaveReplay(PathBuf),
     Flush,
-    Kill,
+    Kill(Instant),
     ChangeDir(PathBuf),
+    Template(FilenameTemplate),
+    SetVar(String, String),
+    Metadata(String, String),
+    Sidecar(bool),
+    /// The transfer applied on the saving thread, and the one
+    /// float samples end up encoded with wherever it runs.
+    Transfer(Option<Transfer>, Transfer),
+    Alpha(AlphaMode),
+    Region(Option<Region>),
+    Thumbnail(Option<Thumbnail>),
+    Threads(usize),
+    Policy(OutputPolicy),
+    Sink(Box<dyn FrameSink>),
 }
 
 #[derive(Default, Debug, Clone)]
@@ -54,40 +283,275 @@ struct Vertex {
 
 vk::impl_vertex!(Vertex, position);
 
-fn save_images(mut screenshot: ShotWriter, save_in: Receiver<Msg>, save_out: Sender<Buffer>) {
-    let mut q = VecDeque::new();
-    while let Ok(msg) = save_in.recv() {
-        match msg {
-            Msg::Buffer(image) => {
-                q.push_back(image);
-                if q.len() > 2 {
-                    let image = q.pop_front().unwrap();
-                    screenshot.save(image.clone());
-                    save_out.send(image).ok();
+/// Sent back by the saving thread for every frame it was handed.
+enum Ack {
+    Written {
+        /// Time spent in the sink.
+        encode: Duration,
+        /// Bytes the sink reported writing for the frame.
+        bytes: u64,
+    },
+    /// The sink wrote this many bytes while flushing, such as a trailer.
+    /// Not a frame.
+    Flushed(u64),
+    /// The GPU did not release the buffer in time.
+    Lost,
+    /// The sink returned an error.
+    Failed,
+    /// A placeholder counted by `Shots`, such as an instant replay,
+    /// turned out to be this many frames.
+    Queued(usize),
+}
+
+/// What `Shots::capture` does when the saving thread has not returned any buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CapturePolicy {
+    /// Wait for a buffer, so no frame is lost but the render loop stalls.
+    Block,
+    /// Skip the frame.
+    Drop,
+    /// Allocate up to this many buffers beyond `Shots::max_buffers`, then skip frames.
+    Queue(usize),
+}
+
+impl Default for CapturePolicy {
+    fn default() -> Self {
+        CapturePolicy::Block
+    }
+}
+
+/// What `Shots::flush` managed to write.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlushReport {
+    /// Frames written while flushing.
+    pub written: usize,
+    /// Frames that could not be read back before the timeout.
+    pub lost: usize,
+    /// Frames the sink failed to write, see `Shots::errors`.
+    pub failed: usize,
+    /// False if the saving thread was still busy when the timeout ran out.
+    pub completed: bool,
+}
+
+/// Sent to the saving thread, addressed to the output of one `Shots`.
+pub(crate) enum Envelope<B> {
+    Attach(usize, ShotWriter, Sender<B>),
+    To(usize, Msg<B>),
+}
+
+/// The frames and settings of one `Shots` on the saving thread.
+struct Output<B> {
+    screenshot: ShotWriter,
+    /// Returns buffers to `Shots` once they are written.
+    save_out: Sender<B>,
+    q: VecDeque<Queued<B>>,
+    poster: Option<(tile::Poster, Shot)>,
+}
+
+/// A capture held back until the GPU has had time to write it. Buffers are
+/// only locked once the frame is submitted, after `view` returns, so reading
+/// them any earlier succeeds with the previous contents.
+enum Queued<B> {
+    /// A frame for the sink, the instant replay or both.
+    Frame(B, SystemTime, Option<Shot>),
+    Tile(B, Tile, Shot),
+    Layers(Vec<(String, B)>, Transfer, Shot),
+    Offscreen(B, Shot),
+}
+
+/// Starts a saving thread that `Shots` can attach their outputs to.
+pub(crate) fn spawn_saving_thread() -> (Sender<Envelope<Buffer>>, JoinHandle<()>) {
+    let (images_out, save_in) = mpsc::channel();
+    let saving_thread = thread::spawn(move || save_images(save_in));
+    (images_out, saving_thread)
+}
+
+/// Runs the saving thread until every sender is gone.
+fn save_images<B: ImageData>(save_in: Receiver<Envelope<B>>) {
+    let mut outputs = HashMap::new();
+    while let Ok(envelope) = save_in.recv() {
+        match envelope {
+            Envelope::Attach(id, screenshot, save_out) => {
+                let output = Output {
+                    screenshot,
+                    save_out,
+                    q: VecDeque::new(),
+                    poster: None,
+                };
+                outputs.insert(id, output);
+            }
+            Envelope::To(id, msg) => {
+                let open = outputs
+                    .get_mut(&id)
+                    .map_or(true, |output| output.handle(msg));
+                if !open {
+                    outputs.remove(&id);
                 }
             }
-            Msg::Flush => {
-                while let Some(image) = q.pop_front() {
-                    screenshot.save(image.clone());
-                    save_out.send(image).ok();
+        }
+    }
+}
+
+impl<B: ImageData> Output<B> {
+    /// Writes everything queued, waiting for the GPU until `deadline`
+    fn write_queued(&mut self, deadline: Instant) {
+        self.write_oldest(self.q.len(), deadline);
+    }
+
+    fn write_oldest(&mut self, n: usize, deadline: Instant) {
+        for _ in 0..n {
+            match self.q.pop_front() {
+                Some(Queued::Frame(image, time, shot)) => {
+                    if let Some(shot) = shot {
+                        self.screenshot.save(&image, shot, deadline);
+                    }
+                    self.screenshot.keep_for_replay(time, &image, deadline);
+                    self.save_out.send(image).ok();
                 }
+                Some(Queued::Tile(image, tile, shot)) => {
+                    self.stitch(image, tile, shot, deadline);
+                }
+                // Layer buffers were allocated for this shot and are dropped once written
+                Some(Queued::Layers(layers, transfer, shot)) => {
+                    self.screenshot
+                        .save_layers(&layers, transfer, shot, deadline);
+                }
+                // Sized for the target, so not returned to be captured into again
+                Some(Queued::Offscreen(image, shot)) => {
+                    self.screenshot.save(&image, shot, deadline);
+                }
+                None => return,
+            }
+        }
+    }
+
+    /// Queues a capture, writing the oldest once it is two frames old.
+    fn queue(&mut self, queued: Queued<B>) {
+        self.q.push_back(queued);
+        if self.q.len() > 2 {
+            self.write_oldest(1, Instant::now() + GPU_WAIT);
+        }
+    }
+
+    /// Copies a tile into the poster, which is written once the last one is in.
+    fn stitch(&mut self, image: B, tile: Tile, shot: Shot, deadline: Instant) {
+        if tile.index() == 0 {
+            if self.poster.take().is_some() {
+                self.screenshot.acks.send(Ack::Lost).ok();
+            }
+            let dims = image.dims();
+            let poster = tile::Poster::new(tile, dims, image.sample_format());
+            self.poster = Some((poster, shot));
+        }
+        let copied = match &mut self.poster {
+            Some((stitched, _)) if stitched.fits(&image) => stitched.copy(tile, &image, deadline),
+            Some(_) => Err("the window size or format changed"),
+            None => Ok(()),
+        };
+        self.save_out.send(image).ok();
+        if let Err(reason) = copied {
+            let index = tile.index();
+            let error = ScreenshotError::Tile { index, reason };
+            self.screenshot.errors.send(error).ok();
+            self.screenshot.acks.send(Ack::Lost).ok();
+            self.poster = None;
+        } else if tile.is_last() {
+            if let Some((stitched, shot)) = self.poster.take() {
+                self.screenshot.save(&stitched, shot, Instant::now());
+            }
+        }
+    }
+
+    /// Handles one message, returning false once the output is closed.
+    fn handle(&mut self, msg: Msg<B>) -> bool {
+        match msg {
+            Msg::Buffer(image, shot) => {
+                self.queue(Queued::Frame(image, shot.time, Some(shot)));
+            }
+            Msg::Tile(image, tile, shot) => self.queue(Queued::Tile(image, tile, shot)),
+            Msg::Layers(layers, transfer, shot) => {
+                self.queue(Queued::Layers(layers, transfer, shot));
+            }
+            Msg::Offscreen(image, shot) => self.queue(Queued::Offscreen(image, shot)),
+            Msg::Replay(image, time) => self.queue(Queued::Frame(image, time, None)),
+            Msg::ReplayLength(length) => match (length, &mut self.screenshot.replay) {
+                (Some(length), Some(replay)) => replay.set_length(length),
+                (length, replay) => *replay = length.map(replay::Replay::new),
+            },
+            Msg::SaveReplay(dir) => {
+                self.write_queued(Instant::now() + GPU_WAIT);
+                self.screenshot.save_replay(dir);
             }
-            Msg::Kill => {
-                while let Some(image) = q.pop_front() {
-                    screenshot.save(image.clone());
-                    save_out.send(image).ok();
+            // Streams stay open, so a recording starting
+            // right after a shot is not split in two
+            Msg::Flush => self.write_queued(Instant::now() + GPU_WAIT),
+            Msg::Kill(deadline) => {
+                self.write_queued(deadline);
+                // A poster still missing tiles will never be finished
+                if self.poster.take().is_some() {
+                    self.screenshot.acks.send(Ack::Lost).ok();
                 }
-                return ();
+                self.screenshot.flush_sink();
+                return false;
             }
             Msg::ChangeDir(dir) => {
-                while let Some(image) = q.pop_front() {
-                    screenshot.save(image.clone());
-                    save_out.send(image).ok();
-                }
-                screenshot.output_dir = dir;
-                screenshot.num_images = 0;
+                self.write_queued(Instant::now() + GPU_WAIT);
+                self.screenshot.flush_sink();
+                self.screenshot.enter_dir(dir);
+            }
+            // Frames queued before a template or variable change
+            // keep the names they were captured under
+            Msg::Template(template) => {
+                self.write_queued(Instant::now() + GPU_WAIT);
+                self.screenshot.template = template;
+            }
+            Msg::SetVar(name, value) => {
+                self.write_queued(Instant::now() + GPU_WAIT);
+                self.screenshot.vars.insert(name, value);
+            }
+            Msg::Metadata(key, value) => {
+                self.write_queued(Instant::now() + GPU_WAIT);
+                self.screenshot.metadata.insert(key, value);
+            }
+            Msg::Sidecar(sidecar) => {
+                self.write_queued(Instant::now() + GPU_WAIT);
+                self.screenshot.sidecar = sidecar;
+            }
+            // Frames already captured were read back for the old pipeline
+            Msg::Transfer(transfer, float_transfer) => {
+                self.write_queued(Instant::now() + GPU_WAIT);
+                self.screenshot.cpu_transfer = transfer;
+                self.screenshot.float_transfer = float_transfer;
+            }
+            Msg::Alpha(alpha) => {
+                self.write_queued(Instant::now() + GPU_WAIT);
+                self.screenshot.alpha = alpha;
+            }
+            Msg::Region(region) => {
+                self.write_queued(Instant::now() + GPU_WAIT);
+                self.screenshot.region = region;
+            }
+            Msg::Thumbnail(thumbnail) => {
+                self.write_queued(Instant::now() + GPU_WAIT);
+                self.screenshot.thumbnail = thumbnail;
+            }
+            Msg::Policy(policy) => {
+                self.write_queued(Instant::now() + GPU_WAIT);
+                self.screenshot.policy = policy;
+            }
+            Msg::Sink(sink) => {
+                self.write_queued(Instant::now() + GPU_WAIT);
+                self.screenshot.flush_sink();
+                self.screenshot.sink = sink;
+                self.screenshot.start_pool();
+            }
+            Msg::Threads(threads) => {
+                self.write_queued(Instant::now() + GPU_WAIT);
+                self.screenshot.threads = threads;
+                self.screenshot.start_pool();
             }
         }
+        true
     }
 }
 
@@ -96,186 +560,2099 @@ impl Shots {
     ///
     /// Captured images are saved into `{basedir}/dist/{subdir?}`.
     /// You can define `subdir` with `Shots::output_dir("subdir")`.
+    /// Numbering continues after existing captures, see `Shots::output_policy`.
     ///
     /// ## Basic usage
-    /// ```
-    /// let screenshot = Shots::new(app, window_id, env!("CARGO_MANIFEST_DIR"));
-    /// screenshot.output_dir("subdir"); // make output dir
-    /// screenshot.capture(&frame); // in view function
+    /// ```no_run
+    /// # use nannou::prelude::*;
+    /// # use screenshot::{ScreenshotError, Shots};
+    /// # fn model(app: &App, window_id: WindowId, frame: &Frame) -> Result<(), ScreenshotError> {
+    /// let mut screenshot = Shots::new(app, window_id, env!("CARGO_MANIFEST_DIR"))?;
+    /// screenshot.output_dir("subdir")?; // make output dir
+    /// screenshot.capture(frame)?; // in view function
     /// screenshot.take(); // save screenshot
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(app: &App, window_id: WindowId, basedir: &str) -> Result<Self, ScreenshotError> {
+        Shots::with_sink(app, window_id, basedir, PngSink)
+    }
+
+    /// Same as `Shots::new` but hands captured frames to `sink`
+    /// instead of writing PNG files.
+    ///
+    /// ```no_run
+    /// # use nannou::prelude::*;
+    /// # use screenshot::{FrameInfo, ScreenshotError, Shots};
+    /// # fn model(app: &App, window_id: WindowId) -> Result<Shots, ScreenshotError> {
+    /// let screenshot = Shots::with_sink(app, window_id, env!("CARGO_MANIFEST_DIR"),
+    ///     |info: &FrameInfo, data: &[u8]| {
+    ///         println!("frame {} ({} bytes)", info.number, data.len());
+    ///         Ok(())
+    ///     })?;
+    /// # Ok(screenshot)
+    /// # }
     /// ```
-    pub fn new(app: &App, window_id: WindowId, basedir: &str) -> Self {
-        let window = app.window(window_id).expect("Failed to get window");
+    pub fn with_sink<S>(
+        app: &App,
+        window_id: WindowId,
+        basedir: &str,
+        sink: S,
+    ) -> Result<Self, ScreenshotError>
+    where
+        S: FrameSink + 'static,
+    {
+        let (images_out, saving_thread) = spawn_saving_thread();
+        let mut shots = Shots::attach(app, window_id, basedir, sink, images_out, 0)?;
+        shots.saving_thread = Some(saving_thread);
+        Ok(shots)
+    }
+
+    /// Sets up capture of `window_id` on the saving thread behind
+    /// `images_out`, addressing its messages with `id`.
+    pub(crate) fn attach<S>(
+        app: &App,
+        window_id: WindowId,
+        basedir: &str,
+        sink: S,
+        images_out: Sender<Envelope<Buffer>>,
+        id: usize,
+    ) -> Result<Self, ScreenshotError>
+    where
+        S: FrameSink + 'static,
+    {
+        let window = app
+            .window(window_id)
+            .ok_or(ScreenshotError::WindowNotFound)?;
         let queue = window.swapchain_queue().clone();
         let dims = {
             let d = window.inner_size_pixels();
             (d.0 as usize, d.1 as usize)
         };
         let (save_out, images_in) = mpsc::channel();
-        let (images_out, save_in) = mpsc::channel();
+        let (ack_out, acks) = mpsc::channel();
+        let (error_out, errors) = mpsc::channel();
 
-        for _ in 0..3 {
+        for _ in 0..INITIAL_BUFFERS {
             let output_image = Buffer {
-                buffer: new_screenshot_buffer(queue.device().clone(), (dims.0, dims.1)),
+                buffer: new_screenshot_buffer(
+                    queue.device().clone(),
+                    (dims.0, dims.1),
+                    SampleFormat::U8,
+                )?,
                 dims,
             };
-            save_out
-                .send(output_image)
-                .expect("Failed to send initial images");
+            save_out.send(output_image).ok();
         }
         // create directory recursively
         let output_dir = Path::new(&basedir).join("dist");
-        std::fs::create_dir_all(&output_dir).expect("Failed to create directory");
-        let shot_writer = ShotWriter {
+        std::fs::create_dir_all(&output_dir)
+            .map_err(ScreenshotError::create_dir(output_dir.clone()))?;
+        let sketch = Path::new(&basedir)
+            .file_name()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut metadata = Metadata::new();
+        metadata.insert("sketch".to_string(), sketch.clone());
+        if let Some(version) = metadata::crate_version(Path::new(&basedir)) {
+            metadata.insert("crate_version".to_string(), version);
+        }
+        if let Some(revision) = metadata::git_revision(Path::new(&basedir)) {
+            metadata.insert("git_revision".to_string(), revision);
+        }
+        let mut vars = HashMap::new();
+        vars.insert("sketch".to_string(), sketch);
+        let policy = OutputPolicy::default();
+        let mut shot_writer = ShotWriter {
             num_images: 0,
-            output_dir: output_dir,
+            output_dir: output_dir.clone(),
+            sink: Box::new(sink),
+            template: FilenameTemplate::default(),
+            vars,
+            policy,
+            metadata,
+            sidecar: false,
+            cpu_transfer: None,
+            float_transfer: Transfer::Linear,
+            alpha: AlphaMode::default(),
+            region: None,
+            thumbnail: None,
+            replay: None,
+            threads: 1,
+            pool: None,
+            errors: error_out,
+            acks: ack_out,
         };
-        let saving_thread = thread::spawn({ || save_images(shot_writer, save_in, save_out) });
-        let saving_thread = Some(saving_thread);
+        shot_writer.enter_dir(output_dir.clone());
         let frame_capture = RefCell::new(capture::FrameCapture::new(
             queue.device().clone(),
             window.msaa_samples(),
             [dims.0 as u32, dims.1 as u32],
-        ));
-        Shots {
+            SampleFormat::U8,
+        )?);
+        images_out
+            .send(Envelope::Attach(id, shot_writer, save_out))
+            .map_err(|_| ScreenshotError::SavingThreadStopped)?;
+        Ok(Shots {
             num_shots: Cell::new(0),
-            frames_since_empty: Cell::new(3),
+            shot_requests: RefCell::new(VecDeque::new()),
+            recording: Cell::new(Recording::Off),
+            tile: Cell::new(None),
+            layered_shots: Cell::new(0),
+            timelapse: Cell::new(None),
+            trigger: Cell::new(trigger::Trigger::new(1)),
+            replay: Cell::new(false),
+            sequence_ending: Cell::new(false),
+            num_buffers: Cell::new(INITIAL_BUFFERS),
+            max_buffers: DEFAULT_MAX_BUFFERS,
+            capture_policy: CapturePolicy::Block,
+            stats: Cell::new(Stats::default()),
+            stats_log: RefCell::new(None),
+            // Past the flush, as nothing was captured yet
+            frames_since_empty: Cell::new(4),
             images_in,
             images_out,
-            saving_thread,
+            acks,
+            errors,
+            pending: Cell::new(0),
+            id,
+            saving_thread: None,
             frame_capture,
+            offscreen: RefCell::new(None),
+            layer_capture: RefCell::new(None),
             basedir: basedir.to_string(),
-        }
+            root: output_dir,
+            subdir: String::new(),
+            policy,
+            sample: SampleFormat::U8,
+            format_sample: SampleFormat::U8,
+            transfer: None,
+            transfer_stage: TransferStage::default(),
+            alpha: AlphaMode::default(),
+        })
     }
 
-    pub fn output_dir(&mut self, subdir: &str) {
+    pub fn output_dir(&mut self, subdir: &str) -> Result<(), ScreenshotError> {
         // create directory recursively
-        let output_dir = Path::new(&self.basedir).join("dist").join(subdir);
-        std::fs::create_dir_all(&output_dir).expect("Failed to create directory");
+        let output_dir = self.root.join(subdir);
+        std::fs::create_dir_all(&output_dir)
+            .map_err(ScreenshotError::create_dir(output_dir.clone()))?;
+        self.subdir = subdir.to_string();
 
-        self.images_out.send(Msg::ChangeDir(output_dir)).ok();
+        self.send(Msg::ChangeDir(output_dir))
     }
 
-    pub fn capture(&self, frame: &Frame) {
-        let num_shots = self.num_shots.get();
+    /// Chooses how existing captures are protected, `OutputPolicy::Resume` by default.
+    ///
+    /// With `OutputPolicy::SessionDir` a new `{basedir}/dist/{timestamp}` directory
+    /// is created, so call this right after `Shots::new`.
+    pub fn output_policy(&mut self, policy: OutputPolicy) -> Result<(), ScreenshotError> {
+        if policy == self.policy {
+            return Ok(());
+        }
+        let dist = Path::new(&self.basedir).join("dist");
+        self.root = match policy {
+            OutputPolicy::SessionDir => output::create_session_dir(&dist)
+                .map_err(ScreenshotError::create_dir(dist.clone()))?,
+            _ => dist,
+        };
+        self.policy = policy;
+        self.send(Msg::Policy(policy))?;
+        let subdir = self.subdir.clone();
+        self.output_dir(&subdir)
+    }
+
+    /// Replaces the sink for following shots.
+    pub fn set_sink<S>(&self, sink: S) -> Result<(), ScreenshotError>
+    where
+        S: FrameSink + 'static,
+    {
+        self.send(Msg::Sink(Box::new(sink)))
+    }
+
+    /// Writes following shots in `format` through an `ImageSink`,
+    /// reading frames back at the precision the format needs.
+    ///
+    /// ```no_run
+    /// # use screenshot::{OutputFormat, ScreenshotError, Shots};
+    /// # fn formats(screenshot: &mut Shots) -> Result<(), ScreenshotError> {
+    /// screenshot.output_format(OutputFormat::Jpeg { quality: 90 })?;
+    /// screenshot.output_format(OutputFormat::Exr)?; // linear float readback
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn output_format(&mut self, format: OutputFormat) -> Result<(), ScreenshotError> {
+        self.format_sample = format.sample_format();
+        self.update_pipeline()?;
+        self.set_sink(ImageSink::new(format))
+    }
+
+    /// Sets how the linear frame is encoded before it is saved.
+    ///
+    /// Unless set, integer formats are sRGB encoded and float formats stay linear.
+    ///
+    /// ```no_run
+    /// # use screenshot::{ScreenshotError, Shots, Transfer};
+    /// # fn gamma(screenshot: &mut Shots) -> Result<(), ScreenshotError> {
+    /// screenshot.transfer(Transfer::Gamma(2.2))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn transfer(&mut self, transfer: Transfer) -> Result<(), ScreenshotError> {
+        self.transfer = Some(transfer);
+        self.update_pipeline()
+    }
+
+    /// Chooses whether the transfer function runs in the readback
+    /// shader or on the saving thread, see `TransferStage`.
+    pub fn transfer_stage(&mut self, stage: TransferStage) -> Result<(), ScreenshotError> {
+        self.transfer_stage = stage;
+        self.update_pipeline()
+    }
+
+    /// Picks the readback precision and where encoding
+    /// happens from the output format and transfer settings.
+    fn update_pipeline(&mut self) -> Result<(), ScreenshotError> {
+        let transfer = self.transfer.unwrap_or(match self.format_sample {
+            SampleFormat::F32 => Transfer::Linear,
+            _ => Transfer::Srgb,
+        });
+        // Alpha is resolved on linear values, so the transfer has to follow it on the CPU
+        let stage = match self.alpha {
+            AlphaMode::Keep => self.transfer_stage,
+            _ => TransferStage::Cpu,
+        };
+        let (sample, gpu_transfer, cpu_transfer) = match stage {
+            TransferStage::Gpu => (self.format_sample, transfer, None),
+            TransferStage::Cpu => (SampleFormat::F32, Transfer::Linear, Some(transfer)),
+        };
+        let mut frame_capture = self.frame_capture.borrow_mut();
+        if sample != self.sample {
+            frame_capture.set_sample_format(sample)?;
+            self.sample = sample;
+        }
+        frame_capture.set_transfer(gpu_transfer);
+        self.send(Msg::Transfer(cpu_transfer, transfer))
+    }
+
+    /// Sets how alpha is treated before frames are encoded, see `AlphaMode`.
+    ///
+    /// Trails drawn with a nearly transparent clear, such as
+    /// `rgba(0.0, 0.0, 0.0, 0.002)`, save as almost invisible
+    /// images unless they are made opaque or flattened.
+    ///
+    /// Any mode but `AlphaMode::Keep` reads frames back as linear floats
+    /// and applies the transfer function on the saving thread afterwards,
+    /// as with `TransferStage::Cpu`.
+    ///
+    /// ```no_run
+    /// # use screenshot::{AlphaMode, ScreenshotError, Shots};
+    /// # fn white(screenshot: &mut Shots) -> Result<(), ScreenshotError> {
+    /// screenshot.alpha_mode(AlphaMode::Flatten([1.0, 1.0, 1.0]))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn alpha_mode(&mut self, mode: AlphaMode) -> Result<(), ScreenshotError> {
+        self.send(Msg::Alpha(mode))?;
+        self.alpha = mode;
+        self.update_pipeline()
+    }
+
+    /// Saves only `region` of following frames, or the whole frame for `None`.
+    ///
+    /// The region is clipped to the frame and applies to posters as a whole.
+    /// Frames it does not overlap are saved uncropped.
+    ///
+    /// ```no_run
+    /// # use nannou::prelude::*;
+    /// # use screenshot::{Region, ScreenshotError, Shots};
+    /// # fn hide_hud(app: &App, screenshot: &Shots) -> Result<(), ScreenshotError> {
+    /// // Leave out a 40 pixel HUD strip at the bottom
+    /// let (w, h) = app.main_window().inner_size_pixels();
+    /// screenshot.crop(Some(Region::new(0, 0, w as usize, h as usize - 40)))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn crop(&self, region: Option<Region>) -> Result<(), ScreenshotError> {
+        self.send(Msg::Region(region))
+    }
+
+    /// Writes a downscaled 8 bit PNG next to every following image,
+    /// e.g. `frame_0001_thumb.png`, or stops for `None`.
+    ///
+    /// Thumbnails are written after their image, and only if it was written.
+    /// Linear float frames, such as EXR, are sRGB encoded for the thumbnail.
+    ///
+    /// ```no_run
+    /// # use screenshot::{Filter, ScreenshotError, Shots, Thumbnail};
+    /// # fn previews(screenshot: &Shots) -> Result<(), ScreenshotError> {
+    /// screenshot.thumbnails(Some(Thumbnail::new(256, 256).filter(Filter::Lanczos3)))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn thumbnails(&self, thumbnail: Option<Thumbnail>) -> Result<(), ScreenshotError> {
+        self.send(Msg::Thumbnail(thumbnail))
+    }
+
+    /// Sets the file name pattern for following shots,
+    /// e.g. `"{sketch}_{seed}_{frame:05}_{timestamp}.{ext}"`.
+    /// See `FilenameTemplate` for the syntax.
+    pub fn filename_template(&self, template: &str) -> Result<(), ScreenshotError> {
+        let template = FilenameTemplate::parse(template)?;
+        self.send(Msg::Template(template))
+    }
+
+    /// Sets a template variable such as `seed` or `preset` for following shots.
+    pub fn set_var<V: ToString>(&self, name: &str, value: V) -> Result<(), ScreenshotError> {
+        self.send(Msg::SetVar(name.to_string(), value.to_string()))
+    }
+
+    /// Adds `key` to the metadata of following shots, replacing any earlier value.
+    ///
+    /// PNG files store metadata as text chunks, so keys must be 1 to 79
+    /// printable ASCII characters without leading or trailing spaces, and
+    /// other keys are rejected. See `Shots::metadata_sidecar` for other formats.
+    ///
+    /// ```no_run
+    /// # use screenshot::{ScreenshotError, Shots};
+    /// # struct Model { seed: u64 }
+    /// # fn seed(screenshot: &Shots, model: &Model) -> Result<(), ScreenshotError> {
+    /// screenshot.set_metadata("seed", model.seed)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_metadata<V: ToString>(&self, key: &str, value: V) -> Result<(), ScreenshotError> {
+        if !metadata::valid_key(key) {
+            return Err(ScreenshotError::MetadataKey(key.to_string()));
+        }
+        self.send(Msg::Metadata(key.to_string(), value.to_string()))
+    }
+
+    /// Also writes the metadata of every frame to a JSON file
+    /// next to it, with the extension replaced by `json`.
+    pub fn metadata_sidecar(&self, enabled: bool) -> Result<(), ScreenshotError> {
+        self.send(Msg::Sidecar(enabled))
+    }
+
+    /// Captures `frame` if a shot was taken or a recording is running.
+    ///
+    /// Errors from writing earlier frames are not returned here,
+    /// they are collected by `Shots::errors`.
+    pub fn capture(&self, frame: &Frame) -> Result<(), ScreenshotError> {
+        let mut trigger = self.trigger.get();
+        if trigger.due() {
+            let mut metadata = Metadata::new();
+            metadata.insert("trigger".to_string(), "complete".to_string());
+            self.take_with_metadata(metadata);
+        }
+        self.trigger.set(trigger);
+        if let (Some(mut timelapse), None) = (self.timelapse.get(), self.tile.get()) {
+            if timelapse.due(Instant::now()) {
+                self.take();
+            }
+            if timelapse.is_done() {
+                self.stop_timelapse();
+            } else {
+                self.timelapse.set(Some(timelapse));
+            }
+        }
         let mut frames_since_empty = self.frames_since_empty.get();
+        // Flushed before this frame is captured, so only frames
+        // whose rendering was already submitted are written
+        if frames_since_empty == 3 {
+            self.send(Msg::Flush)?;
+            // Leave the sequence directory, which also flushes the sink,
+            // only once its last frames have had time to finish rendering
+            if self.sequence_ending.replace(false) {
+                let dir = self.root.join(&self.subdir);
+                self.send(Msg::ChangeDir(dir))?;
+            }
+        }
+        let num_shots = self.num_shots.get();
+        let recording = self.recording.get();
         self.frame_capture.borrow().clear();
-        if num_shots > 0 {
-            if let Ok(mut image) = self.images_in.recv() {
-                let [w, h] = frame.swapchain_image().dimensions();
-                let swap_dims = (w as usize, h as usize);
-                if swap_dims != image.dims {
-                    image = Buffer {
-                        buffer: new_screenshot_buffer(frame.queue().device().clone(), swap_dims),
-                        dims: swap_dims,
-                    };
-                    self.frame_capture
-                        .borrow_mut()
-                        .update_images(frame.queue().device().clone(), swap_dims);
+        self.poll_acks();
+        if let Some(tile) = self.tile.get() {
+            if let Some(image) = self.capture_frame(frame)? {
+                let mut metadata = Metadata::new();
+                if tile.index() == 0 {
+                    let tiles = format!("{}x{}", tile.cols, tile.rows);
+                    metadata.insert("tiles".to_string(), tiles);
+                    self.pending.set(self.pending.get() + 1);
+                }
+                self.send(Msg::Tile(image, tile, Shot::new(metadata)))?;
+                self.tile.set(tile.next());
+                if tile.is_last() {
+                    frames_since_empty = 0;
+                }
+            }
+        } else if num_shots > 0 || recording != Recording::Off {
+            if let Some(image) = self.capture_frame(frame)? {
+                let request = match num_shots {
+                    0 => None,
+                    _ => self.shot_requests.borrow_mut().pop_front(),
+                };
+                let (metadata, memory) = request.unwrap_or_default();
+                let shot = Shot {
+                    memory,
+                    recorded: recording != Recording::Off,
+                    ..Shot::new(metadata)
+                };
+                self.send(Msg::Buffer(image, shot))?;
+                self.pending.set(self.pending.get() + 1);
+                // A shot taken while recording is satisfied by the recorded frame
+                self.num_shots.set(num_shots.saturating_sub(1));
+                let recording = match recording {
+                    Recording::Frames(1) => {
+                        self.sequence_ending.set(true);
+                        Recording::Off
+                    }
+                    Recording::Frames(n) => Recording::Frames(n - 1),
+                    recording => recording,
+                };
+                self.recording.set(recording);
+                if num_shots <= 1 && recording == Recording::Off {
+                    frames_since_empty = 0;
                 }
-                self.frame_capture.borrow().capture(frame, image.clone());
-                self.images_out.send(Msg::Buffer(image)).ok();
-                self.num_shots.set(num_shots - 1);
             }
-            if num_shots == 1 {
-                frames_since_empty = 0;
+        } else if self.replay.get() {
+            if let Some(image) = self.capture_frame(frame)? {
+                self.send(Msg::Replay(image, SystemTime::now()))?;
             }
         }
-        if frames_since_empty == 2 {
-            self.images_out.send(Msg::Flush).ok();
-        }
         self.frames_since_empty.set(frames_since_empty + 1);
+        if let Some((interval, last, report)) = &mut *self.stats_log.borrow_mut() {
+            if last.elapsed() >= *interval {
+                report(&self.stats());
+                *last = Instant::now();
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies `frame` into a free buffer of the current size and precision.
+    fn capture_frame(&self, frame: &Frame) -> Result<Option<Buffer>, ScreenshotError> {
+        self.update_stats(|stats| stats.requested += 1);
+        let image = match self.next_buffer(frame)? {
+            Some(image) => image,
+            None => return Ok(None),
+        };
+        let image = self.copy_frame(frame, image)?;
+        self.update_stats(|stats| stats.captured += 1);
+        Ok(Some(image))
+    }
+
+    /// Copies `frame` into `image`, replacing it first if its size or precision is out of date.
+    fn copy_frame(&self, frame: &Frame, mut image: Buffer) -> Result<Buffer, ScreenshotError> {
+        let [w, h] = frame.swapchain_image().dimensions();
+        let swap_dims = (w as usize, h as usize);
+        if swap_dims != image.dims || self.sample != image.sample_format() {
+            image = Buffer {
+                buffer: new_screenshot_buffer(
+                    frame.queue().device().clone(),
+                    swap_dims,
+                    self.sample,
+                )?,
+                dims: swap_dims,
+            };
+        }
+        if swap_dims != self.frame_capture.borrow().dims() {
+            self.frame_capture
+                .borrow_mut()
+                .update_images(frame.queue().device().clone(), swap_dims)?;
+        }
+        self.frame_capture.borrow().capture(frame, image.clone())?;
+        Ok(image)
+    }
+
+    /// Takes a free buffer, growing the pool up to `max_buffers`
+    /// before waiting on the saving thread or dropping the frame,
+    /// depending on the `CapturePolicy`.
+    fn next_buffer(&self, frame: &Frame) -> Result<Option<Buffer>, ScreenshotError> {
+        if let Ok(image) = self.images_in.try_recv() {
+            return Ok(Some(image));
+        }
+        let num_buffers = self.num_buffers.get();
+        let max_buffers = match self.capture_policy {
+            CapturePolicy::Queue(extra) => self.max_buffers + extra,
+            _ => self.max_buffers,
+        };
+        if num_buffers < max_buffers {
+            self.num_buffers.set(num_buffers + 1);
+            let [w, h] = frame.swapchain_image().dimensions();
+            let dims = (w as usize, h as usize);
+            return Ok(Some(Buffer {
+                buffer: new_screenshot_buffer(frame.queue().device().clone(), dims, self.sample)?,
+                dims,
+            }));
+        }
+        match self.capture_policy {
+            CapturePolicy::Block => Ok(self.images_in.recv().ok()),
+            _ => {
+                self.update_stats(|stats| stats.dropped += 1);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Starts capturing every frame until `Shots::stop_recording`.
+    ///
+    /// Each recording is written as its own numbered sequence into
+    /// a new `recording{n}` directory inside the current output directory.
+    pub fn start_recording(&self) -> Result<(), ScreenshotError> {
+        if self.recording.get() == Recording::Off {
+            self.begin_sequence("recording")?;
+        }
+        self.recording.set(Recording::On);
+        Ok(())
+    }
+
+    pub fn stop_recording(&self) {
+        if self.recording.get() != Recording::Off {
+            self.recording.set(Recording::Off);
+            self.sequence_ending.set(true);
+            self.frames_since_empty.set(0);
+        }
+    }
+
+    /// Records the next `n` frames, then stops.
+    pub fn record_frames(&self, n: usize) -> Result<(), ScreenshotError> {
+        if n == 0 {
+            self.stop_recording();
+            return Ok(());
+        }
+        if self.recording.get() == Recording::Off {
+            self.begin_sequence("recording")?;
+        }
+        self.recording.set(Recording::Frames(n));
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.get() != Recording::Off
+    }
+
+    /// Limits how many screenshot buffers may be in flight, `DEFAULT_MAX_BUFFERS` by default.
+    ///
+    /// When the saving thread falls behind, `capture` allocates new buffers up to
+    /// this limit and then follows the `CapturePolicy`.
+    pub fn max_buffers(&mut self, max: usize) {
+        self.max_buffers = max.max(INITIAL_BUFFERS);
+    }
+
+    /// Chooses what `capture` does once every buffer is in use, `CapturePolicy::Block` by default.
+    pub fn capture_policy(&mut self, policy: CapturePolicy) {
+        self.capture_policy = policy;
+    }
+
+    /// Number of frames skipped by `CapturePolicy::Drop` or `CapturePolicy::Queue`.
+    ///
+    /// A dropped shot is retried on the next frame, a dropped recording frame is gone.
+    pub fn dropped(&self) -> usize {
+        self.stats.get().dropped
+    }
+
+    /// Counters of everything captured and written so far.
+    ///
+    /// ```no_run
+    /// # use nannou::prelude::*;
+    /// # use screenshot::Shots;
+    /// # struct Model { screenshot: Shots }
+    /// # fn update(_app: &App, model: &mut Model, _update: Update) {
+    /// let stats = model.screenshot.stats();
+    /// if stats.dropped + stats.lost + stats.failed > 0 {
+    ///     eprintln!("recording is incomplete: {}", stats);
+    /// }
+    /// # }
+    /// ```
+    pub fn stats(&self) -> Stats {
+        self.poll_acks();
+        let mut stats = self.stats.get();
+        stats.queue_depth = self.pending.get();
+        stats
+    }
+
+    /// Calls `report` with `Shots::stats` from `capture` every `interval`,
+    /// replacing any earlier one.
+    ///
+    /// ```no_run
+    /// # use screenshot::Shots;
+    /// # use std::time::Duration;
+    /// # fn report(screenshot: &Shots) {
+    /// screenshot.log_stats(Duration::from_secs(5), |stats| println!("screenshot: {}", stats));
+    /// # }
+    /// ```
+    pub fn log_stats<F>(&self, interval: Duration, report: F)
+    where
+        F: FnMut(&Stats) + Send + 'static,
+    {
+        *self.stats_log.borrow_mut() = Some((interval, Instant::now(), Box::new(report)));
+    }
+
+    /// Stops calling the function given to `Shots::log_stats`.
+    pub fn stop_stats_log(&self) {
+        *self.stats_log.borrow_mut() = None;
+    }
+
+    fn update_stats<F: FnOnce(&mut Stats)>(&self, f: F) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    /// Encodes frames on `threads` threads so recordings keep up with the
+    /// render loop. Files are still numbered in capture order.
+    ///
+    /// Only sinks that return a copy from `FrameSink::try_clone`, like
+    /// `PngSink` and `ImageSink`, are encoded in parallel, others stay serial.
+    /// The same threads compress the frames kept by `Shots::instant_replay`.
+    ///
+    /// ```no_run
+    /// # use screenshot::{ScreenshotError, Shots};
+    /// # fn parallel(screenshot: &Shots) -> Result<(), ScreenshotError> {
+    /// screenshot.encoding_threads(4)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn encoding_threads(&self, threads: usize) -> Result<(), ScreenshotError> {
+        self.send(Msg::Threads(threads.max(1)))
+    }
+
+    /// Takes a shot at every `interval` until stopped or `max` shots were taken.
+    ///
+    /// Like a recording, each timelapse is written into a new
+    /// `timelapse{n}` directory inside the current output directory.
+    ///
+    /// ```no_run
+    /// # use screenshot::{Interval, ScreenshotError, Shots};
+    /// # use std::time::Duration;
+    /// # fn hourly(screenshot: &Shots) -> Result<(), ScreenshotError> {
+    /// screenshot.start_timelapse(Interval::Every(Duration::from_secs(10)), Some(360))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn start_timelapse(
+        &self,
+        interval: Interval,
+        max: Option<usize>,
+    ) -> Result<(), ScreenshotError> {
+        if max == Some(0) {
+            self.stop_timelapse();
+            return Ok(());
+        }
+        if self.timelapse.get().is_none() {
+            self.begin_sequence("timelapse")?;
+        }
+        self.timelapse
+            .set(Some(timelapse::Timelapse::new(interval, max)));
+        Ok(())
+    }
+
+    pub fn stop_timelapse(&self) {
+        if self.timelapse.take().is_some() {
+            self.sequence_ending.set(true);
+            self.frames_since_empty.set(0);
+        }
+    }
+
+    /// Starts a timelapse if none is running, otherwise stops it.
+    /// Meant to be bound to a key.
+    pub fn toggle_timelapse(
+        &self,
+        interval: Interval,
+        max: Option<usize>,
+    ) -> Result<(), ScreenshotError> {
+        if self.is_timelapse() {
+            self.stop_timelapse();
+            Ok(())
+        } else {
+            self.start_timelapse(interval, max)
+        }
+    }
+
+    pub fn is_timelapse(&self) -> bool {
+        self.timelapse.get().is_some()
+    }
+
+    /// Saves the final image of a generated piece without timing a keypress.
+    ///
+    /// Call this every frame with whether the piece is finished. A shot is
+    /// taken the first time `done` holds and again only after it was false,
+    /// so each piece is saved once. See `Shots::settle_frames` for the delay.
+    ///
+    /// ```no_run
+    /// # use nannou::prelude::*;
+    /// # use screenshot::Shots;
+    /// # struct Circles;
+    /// # impl Circles { fn is_que_empty(&self) -> bool { true } }
+    /// # struct Model { screenshot: Shots, circles: Circles }
+    /// fn view(app: &App, model: &Model, frame: &Frame) {
+    ///     // ...
+    ///     model.screenshot.capture_when(model.circles.is_que_empty());
+    ///     model.screenshot.capture(frame).unwrap();
+    /// }
+    /// ```
+    pub fn capture_when(&self, done: bool) {
+        let mut trigger = self.trigger.get();
+        trigger.update(done);
+        self.trigger.set(trigger);
+    }
+
+    /// Saves a shot once the settle delay has passed, for sketches
+    /// that know the moment they draw their last element.
+    ///
+    /// ```no_run
+    /// # use screenshot::Shots;
+    /// # struct Circles;
+    /// # impl Circles {
+    /// #     fn add_que(&mut self, n: usize) {}
+    /// #     fn is_que_empty(&self) -> bool { true }
+    /// # }
+    /// # enum Message { RenderReady, Nothing }
+    /// # struct Model { screenshot: Shots, circles: Circles, message: Message }
+    /// # fn update(model: &mut Model) {
+    /// # match model.message {
+    /// Message::RenderReady => {
+    ///     model.circles.add_que(40);
+    ///     if model.circles.is_que_empty() {
+    ///         model.message = Message::Nothing;
+    ///         model.screenshot.mark_complete();
+    ///     }
+    /// }
+    /// # Message::Nothing => {}
+    /// # }
+    /// # }
+    /// ```
+    pub fn mark_complete(&self) {
+        let mut trigger = self.trigger.get();
+        trigger.fire();
+        self.trigger.set(trigger);
+    }
+
+    /// Frames `Shots::capture_when` and `Shots::mark_complete` wait for
+    /// rendering to settle before taking their shot. Defaults to 1, so the
+    /// frame drawn right after the piece was finished is saved.
+    pub fn settle_frames(&self, frames: usize) {
+        let mut trigger = self.trigger.get();
+        trigger.set_settle(frames);
+        self.trigger.set(trigger);
+    }
+
+    /// Keeps compressed copies of the frames from the last `length`, to
+    /// be saved by `Shots::save_replay`, or stops keeping them for `None`.
+    ///
+    /// Every frame is captured while this is on. Memory is bounded by
+    /// `length`, and by a fixed limit for large windows.
+    ///
+    /// ```no_run
+    /// # use screenshot::{ScreenshotError, Shots};
+    /// # use std::time::Duration;
+    /// # fn keep(screenshot: &Shots) -> Result<(), ScreenshotError> {
+    /// screenshot.instant_replay(Some(Duration::from_secs(10)))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn instant_replay(&self, length: Option<Duration>) -> Result<(), ScreenshotError> {
+        self.replay.set(length.is_some());
+        self.send(Msg::ReplayLength(length))
+    }
+
+    /// Writes the frames kept by `Shots::instant_replay` as a sequence in
+    /// a new `replay{n}` directory inside the current output directory,
+    /// then starts collecting again.
+    pub fn save_replay(&self) -> Result<(), ScreenshotError> {
+        let dir = self.new_sequence_dir("replay")?;
+        self.send(Msg::SaveReplay(dir))?;
+        // Stands in for the replay frames until the saving thread counts them
+        self.pending.set(self.pending.get() + 1);
+        Ok(())
+    }
+
+    fn begin_sequence(&self, name: &str) -> Result<(), ScreenshotError> {
+        self.sequence_ending.set(false);
+        let sequence_dir = self.new_sequence_dir(name)?;
+        self.send(Msg::ChangeDir(sequence_dir))
+    }
+
+    /// Creates the first free `{name}{n}` directory inside the current output directory.
+    fn new_sequence_dir(&self, name: &str) -> Result<PathBuf, ScreenshotError> {
+        let dir = self.root.join(&self.subdir);
+        let mut n = 1;
+        let mut sequence_dir = dir.join(format!("{}1", name));
+        while sequence_dir.exists() {
+            n += 1;
+            sequence_dir = dir.join(format!("{}{}", name, n));
+        }
+        std::fs::create_dir_all(&sequence_dir)
+            .map_err(ScreenshotError::create_dir(sequence_dir.clone()))?;
+        Ok(sequence_dir)
     }
 
     pub fn take(&self) {
+        self.take_with_metadata(Metadata::new());
+    }
+
+    /// Same as `Shots::take`, adding `metadata` to this shot only.
+    ///
+    /// ```no_run
+    /// # use screenshot::{Metadata, Shots};
+    /// # #[derive(Debug)]
+    /// # struct Params { radius: f32 }
+    /// # struct Model { params: Params }
+    /// # fn save(screenshot: &Shots, model: &Model) {
+    /// let mut metadata = Metadata::new();
+    /// metadata.insert("params".to_string(), format!("{:?}", model.params));
+    /// screenshot.take_with_metadata(metadata);
+    /// # }
+    /// ```
+    pub fn take_with_metadata(&self, metadata: Metadata) {
+        self.request_shot(metadata, None);
+    }
+
+    /// Captures the next frame into an image instead of writing it,
+    /// so the sketch can inspect its own output.
+    ///
+    /// The image arrives once the saving thread has read it back, a few frames
+    /// later. The sender is dropped without a message if the frame is lost.
+    /// Alpha and transfer settings apply, and the image is always 8 bit.
+    ///
+    /// ```no_run
+    /// # use screenshot::Shots;
+    /// # fn inspect(screenshot: &Shots) {
+    /// let rx = screenshot.capture_to_memory();
+    /// // ... in a later frame
+    /// if let Ok(image) = rx.try_recv() {
+    ///     let lit = image.pixels().filter(|p| p[0] > 128).count();
+    /// }
+    /// # }
+    /// ```
+    pub fn capture_to_memory(&self) -> Receiver<RgbaImage> {
+        let (tx, rx) = mpsc::channel();
+        self.capture_to_memory_with(move |image| {
+            tx.send(image).ok();
+        });
+        rx
+    }
+
+    /// Same as `Shots::capture_to_memory` but calls `f` with
+    /// the image on the saving thread.
+    pub fn capture_to_memory_with<F>(&self, f: F)
+    where
+        F: FnOnce(RgbaImage) + Send + 'static,
+    {
+        self.request_shot(Metadata::new(), Some(Box::new(f)));
+    }
+
+    fn request_shot(&self, metadata: Metadata, memory: Option<MemoryCallback>) {
+        self.shot_requests
+            .borrow_mut()
+            .push_back((metadata, memory));
         self.num_shots.set(self.num_shots.get() + 1);
     }
 
-    // Call this in the exit function to make sure all images are written
-    pub fn flush(mut self, wait: Duration) {
-        thread::sleep(wait);
-        self.images_out.send(Msg::Kill).ok();
-        self.saving_thread.take().map(|t| t.join());
+    /// Renders the next `cols * rows` frames as tiles of one poster, each
+    /// showing part of the scene scaled up to the window size, and saves
+    /// them stitched together as a single image.
+    ///
+    /// The sketch has to draw each frame through the transform of
+    /// `Shots::tile`. Does nothing while a poster is being captured.
+    ///
+    /// ```no_run
+    /// # use screenshot::Shots;
+    /// # fn poster(screenshot: &Shots) {
+    /// screenshot.take_poster(4, 4); // 4x the window resolution
+    /// # }
+    /// ```
+    pub fn take_poster(&self, cols: usize, rows: usize) {
+        if self.tile.get().is_none() {
+            self.tile.set(Some(Tile {
+                col: 0,
+                row: 0,
+                cols: cols.max(1),
+                rows: rows.max(1),
+            }));
+        }
+    }
+
+    /// Saves the layers passed to the next `Shots::capture_layers`
+    /// as an OpenRaster (`.ora`) file, for reworking in an image editor.
+    pub fn take_layers(&self) {
+        self.layered_shots.set(self.layered_shots.get() + 1);
+    }
+
+    /// Draws `layers` into `frame` bottom first, then captures it like `Shots::capture`.
+    /// Use this in place of `draw.to_frame` and `Shots::capture`.
+    ///
+    /// If a layered shot was taken, every layer is first rendered on its own
+    /// over a transparent background and captured. What the window showed is
+    /// set aside meanwhile and put back before the layers are drawn into it,
+    /// so sketches that fade out the previous frame keep their trails.
+    ///
+    /// ```no_run
+    /// # use nannou::prelude::*;
+    /// # use screenshot::Shots;
+    /// # struct Model { screenshot: Shots }
+    /// fn view(app: &App, model: &Model, frame: &Frame) {
+    ///     let fade = app.draw();
+    ///     fade.rect()
+    ///         .wh(app.window_rect().wh())
+    ///         .color(rgba(0.0, 0.0, 0.0, 0.05));
+    ///     let dot = app.draw();
+    ///     dot.ellipse().x_y(app.mouse.x, app.mouse.y).radius(10.0);
+    ///     let layers = [("fade", &fade), ("dot", &dot)];
+    ///     model.screenshot.capture_layers(app, frame, &layers).unwrap();
+    /// }
+    /// ```
+    pub fn capture_layers(
+        &self,
+        app: &App,
+        frame: &Frame,
+        layers: &[(&str, &Draw)],
+    ) -> Result<(), ScreenshotError> {
+        let layered_shots = self.layered_shots.get();
+        if layered_shots > 0 {
+            self.update_stats(|stats| stats.requested += 1);
+            let device = frame.queue().device().clone();
+            let [w, h] = frame.swapchain_image().dimensions();
+            let dims = (w as usize, h as usize);
+            let mut layer_capture = self.layer_capture.borrow_mut();
+            if layer_capture
+                .as_ref()
+                .map_or(true, |capture| capture.dims() != dims)
+            {
+                // Linear, so layers are composited and un-premultiplied before encoding
+                let msaa_samples = app
+                    .window(frame.window_id())
+                    .map_or(1, |window| window.msaa_samples());
+                let mut capture = capture::FrameCapture::new(
+                    device.clone(),
+                    msaa_samples,
+                    [w, h],
+                    SampleFormat::F32,
+                )?;
+                capture.set_transfer(Transfer::Linear);
+                *layer_capture = Some(capture);
+            }
+            let layer_capture = match layer_capture.as_ref() {
+                Some(capture) => capture,
+                None => return Ok(()),
+            };
+            layer_capture.clear();
+            layer_capture.save_frame(frame)?;
+            let mut captured = Vec::with_capacity(layers.len());
+            for &(name, draw) in layers {
+                frame.clear(rgba(0.0, 0.0, 0.0, 0.0));
+                draw.to_frame(app, frame)
+                    .map_err(ScreenshotError::vulkan("Failed to draw layer"))?;
+                // Buffers of their own, as the pool may hold fewer than there are layers
+                let image = Buffer {
+                    buffer: new_screenshot_buffer(device.clone(), dims, SampleFormat::F32)?,
+                    dims,
+                };
+                layer_capture.capture(frame, image.clone())?;
+                captured.push((name.to_string(), image));
+            }
+            layer_capture.restore_frame(frame)?;
+            let transfer = self.transfer.unwrap_or_default();
+            self.send(Msg::Layers(captured, transfer, Shot::new(Metadata::new())))?;
+            self.update_stats(|stats| stats.captured += 1);
+            self.pending.set(self.pending.get() + 1);
+            self.layered_shots.set(layered_shots - 1);
+        }
+        for &(_, draw) in layers {
+            draw.to_frame(app, frame)
+                .map_err(ScreenshotError::vulkan("Failed to draw layer"))?;
+        }
+        self.capture(frame)
+    }
+
+    /// Renders `view` into an offscreen target of `dims` pixels and saves it
+    /// like a shot, so the export resolution does not depend on the window.
+    ///
+    /// `view` gets a fresh `Draw` and the rect of the target, with one unit
+    /// per pixel. It is called once for every frame sized piece of the target,
+    /// which are rendered into `frame` and copied together on the GPU, so call
+    /// this before drawing the window contents. The target is read back into
+    /// a buffer allocated for the shot and freed once it is written.
+    ///
+    /// ```no_run
+    /// # use nannou::prelude::*;
+    /// # use screenshot::{ScreenshotError, Shots};
+    /// # struct Model { screenshot: Shots, export: bool }
+    /// # fn export(app: &App, model: &Model, frame: &Frame) -> Result<(), ScreenshotError> {
+    /// if model.export {
+    ///     let view = |draw: &Draw, rect: Rect| {
+    ///         draw.background().color(BLACK);
+    ///         draw.ellipse().w_h(rect.w() / 2.0, rect.h() / 2.0).color(WHITE);
+    ///     };
+    ///     model.screenshot.capture_offscreen(app, frame, (4096, 4096), view)?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn capture_offscreen<F>(
+        &self,
+        app: &App,
+        frame: &Frame,
+        dims: (usize, usize),
+        view: F,
+    ) -> Result<(), ScreenshotError>
+    where
+        F: Fn(&Draw, Rect),
+    {
+        self.poll_acks();
+        self.update_stats(|stats| stats.requested += 1);
+        let device = frame.queue().device().clone();
+        // A buffer of its own, dropped once written, so the
+        // pool is not left holding one of the target's size
+        let image = Buffer {
+            buffer: new_screenshot_buffer(device.clone(), dims, self.sample)?,
+            dims,
+        };
+        let [w, h] = frame.swapchain_image().dimensions();
+        let frame_dims = (w as usize, h as usize);
+        if frame_dims != self.frame_capture.borrow().dims() {
+            self.frame_capture
+                .borrow_mut()
+                .update_images(device.clone(), frame_dims)?;
+        }
+        // Points per pixel, so the target can be drawn in pixels
+        let (scale, msaa_samples) = match app.window(frame.window_id()) {
+            Some(window) => (window.rect().w() / w as f32, window.msaa_samples()),
+            None => (1.0, 1),
+        };
+        let mut offscreen = self.offscreen.borrow_mut();
+        let stale = offscreen.as_ref().map_or(true, |target| {
+            target.dims() != dims || target.sample() != self.sample
+        });
+        if stale {
+            let size = [dims.0 as u32, dims.1 as u32];
+            let target = capture::FrameCapture::new(device, msaa_samples, size, self.sample)?;
+            *offscreen = Some(target);
+        }
+        let target = match offscreen.as_mut() {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        target.set_transfer(self.frame_capture.borrow().transfer());
+        let frame_capture = self.frame_capture.borrow();
+        frame_capture.clear();
+        let rect = Rect::from_w_h(dims.0 as f32, dims.1 as f32);
+        for piece in offscreen::pieces(dims, frame_dims) {
+            frame.clear(rgba(0.0, 0.0, 0.0, 0.0));
+            let draw = app.draw();
+            let (x, y) = piece.center;
+            view(&draw.scale(scale).x_y(-x, -y), rect);
+            draw.to_frame(app, frame)
+                .map_err(ScreenshotError::vulkan("Failed to draw offscreen piece"))?;
+            frame_capture.resolve(frame)?;
+            frame_capture.copy_to(frame, target, piece.offset, piece.extent)?;
+        }
+        frame.clear(rgba(0.0, 0.0, 0.0, 0.0));
+        target.read_back(frame, image.clone())?;
+        let mut metadata = Metadata::new();
+        metadata.insert("offscreen".to_string(), format!("{}x{}", dims.0, dims.1));
+        self.send(Msg::Offscreen(image, Shot::new(metadata)))?;
+        self.update_stats(|stats| stats.captured += 1);
+        self.pending.set(self.pending.get() + 1);
+        Ok(())
+    }
+
+    /// The poster tile to render this frame, if `Shots::take_poster` is running.
+    pub fn tile(&self) -> Option<Tile> {
+        self.tile.get()
+    }
+
+    /// Number of captured frames the saving thread has not written yet.
+    pub fn pending(&self) -> usize {
+        self.poll_acks();
+        self.pending.get()
+    }
+
+    /// Errors reported by the saving thread since the last call,
+    /// such as frames a sink failed to write.
+    ///
+    /// ```no_run
+    /// # use nannou::prelude::*;
+    /// # use screenshot::Shots;
+    /// # struct Model { screenshot: Shots }
+    /// # fn update(_app: &App, model: &mut Model, _update: Update) {
+    /// for e in model.screenshot.errors() {
+    ///     eprintln!("screenshot: {}", e);
+    /// }
+    /// # }
+    /// ```
+    pub fn errors(&self) -> impl Iterator<Item = ScreenshotError> + '_ {
+        self.errors.try_iter()
+    }
+
+    fn send(&self, msg: Msg<Buffer>) -> Result<(), ScreenshotError> {
+        self.images_out
+            .send(Envelope::To(self.id, msg))
+            .map_err(|_| ScreenshotError::SavingThreadStopped)
+    }
+
+    fn poll_acks(&self) {
+        for ack in self.acks.try_iter() {
+            self.count_ack(&ack);
+        }
+    }
+
+    /// Updates the pending count and stats for an acknowledged frame.
+    fn count_ack(&self, ack: &Ack) {
+        let pending = match *ack {
+            Ack::Queued(n) => self.pending.get() + n,
+            Ack::Flushed(_) => self.pending.get() + 1,
+            _ => self.pending.get(),
+        };
+        self.pending.set(pending.saturating_sub(1));
+        self.update_stats(|stats| match *ack {
+            Ack::Written { encode, bytes } => {
+                stats.written += 1;
+                stats.encode_time += encode;
+                stats.bytes_written += bytes;
+            }
+            Ack::Lost => stats.lost += 1,
+            Ack::Failed => stats.failed += 1,
+            Ack::Flushed(bytes) => stats.bytes_written += bytes,
+            Ack::Queued(_) => {}
+        });
+    }
+
+    /// Call this in the exit function to make sure all images are written.
+    ///
+    /// Blocks until every captured frame is written or `timeout` runs out.
+    /// Frames the GPU has not finished with by then are reported as lost.
+    ///
+    /// ```no_run
+    /// # use nannou::prelude::*;
+    /// # use screenshot::Shots;
+    /// # use std::time::Duration;
+    /// # struct Model { screenshot: Shots }
+    /// # fn exit(_app: &App, model: Model) {
+    /// let report = model.screenshot.flush(Duration::from_secs(3));
+    /// if report.lost > 0 {
+    ///     eprintln!("{} screenshots were lost", report.lost);
+    /// }
+    /// # }
+    /// ```
+    pub fn flush(mut self, timeout: Duration) -> FlushReport {
+        let deadline = Instant::now() + timeout;
+        self.poll_acks();
+        let mut report = FlushReport::default();
+        self.send(Msg::Kill(deadline)).ok();
+        let acked = |r: &FlushReport| r.written + r.lost + r.failed;
+        while acked(&report) < self.pending.get() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.acks.recv_timeout(remaining) {
+                Ok(Ack::Written { .. }) => report.written += 1,
+                Ok(Ack::Lost) => report.lost += 1,
+                Ok(Ack::Failed) => report.failed += 1,
+                Ok(Ack::Queued(n)) => self.pending.set(self.pending.get() + n - 1),
+                Ok(Ack::Flushed(_)) => {}
+                Err(_) => break,
+            }
+        }
+        let unacked = self.pending.get() - acked(&report);
+        report.lost += unacked;
+        report.completed = unacked == 0;
+        // A thread stuck in a slow sink is left to finish on its own
+        if report.completed {
+            if let Some(saving_thread) = self.saving_thread.take() {
+                // The thread runs until every sender is gone
+                drop(self.images_out);
+                saving_thread.join().ok();
+            }
+        }
+        report
     }
 }
 
 impl ShotWriter {
-    fn save(&mut self, screenshot_buffer: Buffer) {
-        fn write(
-            screenshot_buffer: &[[u8; NUM_COLOURS]],
-            screenshot_path: PathBuf,
-            dims: (usize, usize),
-        ) {
-            let buf: &[u8] = unsafe {
-                slice::from_raw_parts(
-                    &screenshot_buffer[0] as *const u8,
-                    NUM_COLOURS * dims.0 * dims.1,
-                )
-            };
+    fn enter_dir(&mut self, dir: PathBuf) {
+        self.vars
+            .insert("ext".to_string(), self.sink.extension().to_string());
+        self.num_images = match self.policy {
+            OutputPolicy::Overwrite => 0,
+            _ => output::highest_frame(&dir, &self.template, &self.vars),
+        };
+        self.output_dir = dir;
+    }
+
+    fn flush_sink(&mut self) {
+        self.finish_pool();
+        match self.sink.flush() {
+            Ok(0) => {}
+            Ok(bytes) => {
+                self.acks.send(Ack::Flushed(bytes)).ok();
+            }
+            Err(e) => {
+                self.errors.send(ScreenshotError::Sink(e)).ok();
+            }
+        }
+    }
+
+    /// Writes the instant replay frames into `dir`, numbered from 1,
+    /// then returns to the current directory.
+    fn save_replay(&mut self, dir: PathBuf) {
+        // Frames still being compressed belong to the replay
+        self.finish_pool();
+        let mut replay = match self.replay.take() {
+            Some(replay) => replay,
+            None => {
+                self.acks.send(Ack::Queued(0)).ok();
+                return;
+            }
+        };
+        self.acks.send(Ack::Queued(replay.len())).ok();
+        self.flush_sink();
+        let (output_dir, num_images) = (self.output_dir.clone(), self.num_images);
+        self.enter_dir(dir);
+        for frame in replay.drain() {
+            match frame {
+                Ok(frame) => {
+                    let shot = Shot {
+                        time: frame.time,
+                        ..Shot::new(Metadata::new())
+                    };
+                    self.save(&frame, shot, Instant::now());
+                }
+                Err(message) => {
+                    let error = io::Error::new(io::ErrorKind::InvalidData, message);
+                    self.errors.send(ScreenshotError::Sink(error)).ok();
+                    self.acks.send(Ack::Failed).ok();
+                }
+            }
+        }
+        self.flush_sink();
+        self.output_dir = output_dir;
+        self.num_images = num_images;
+        self.replay = Some(replay);
+    }
+
+    /// Converts `image` to 8 bit RGBA and hands it to `memory`.
+    /// Returns `Ack::Lost` if the GPU still held the buffer at `deadline`.
+    fn save_to_memory<B: ImageData>(
+        &self,
+        image: &B,
+        memory: MemoryCallback,
+        deadline: Instant,
+    ) -> Ack {
+        let region = self.region(image.dims());
+        let (w, h) = (region.width, region.height);
+        let sample = image.sample_format();
+        let read = image.read_by(deadline, |data| {
+            let start = Instant::now();
+            let data = region.crop(data, image.dims(), sample);
+            let data = prepare(&data, sample, self.alpha, self.cpu_transfer);
+            (format::to_u8(&data, sample).into_owned(), start)
+        });
+        let (rgba, start) = match read {
+            Some(read) => read,
+            None => return Ack::Lost,
+        };
+        match RgbaImage::from_raw(w as u32, h as u32, rgba) {
+            Some(rgba) => {
+                let encode = start.elapsed();
+                memory(rgba);
+                Ack::Written { encode, bytes: 0 }
+            }
+            None => Ack::Failed,
+        }
+    }
+
+    /// The crop region clipped to a frame of `dims`, or the whole frame.
+    fn region(&self, dims: (usize, usize)) -> Region {
+        self.region
+            .and_then(|region| region.clip(dims))
+            .unwrap_or_else(|| Region::new(0, 0, dims.0, dims.1))
+    }
+
+    /// Numbers and names the next frame, and collects its metadata.
+    fn frame_info(
+        &mut self,
+        dims: (usize, usize),
+        sample: SampleFormat,
+        shot: Shot,
+        extension: &str,
+    ) -> FrameInfo {
+        let mut number = self.num_images + 1;
+        self.vars.insert(
+            "timestamp".to_string(),
+            template::timestamp(SystemTime::now()),
+        );
+        self.vars.insert("ext".to_string(), extension.to_string());
+        let path = loop {
+            self.vars.insert("frame".to_string(), number.to_string());
+            let path = self.output_dir.join(self.template.render(&self.vars));
+            if self.policy == OutputPolicy::Overwrite || !path.exists() {
+                break path;
+            }
+            if !self.template.has_var("frame") {
+                break output::free_path(&path);
+            }
+            number += 1;
+        };
+        let (w, h) = dims;
+        let region = self.region(dims);
+        let mut metadata = Metadata::new();
+        metadata.insert("window_size".to_string(), format!("{}x{}", w, h));
+        if (region.width, region.height) != (w, h) {
+            let Region {
+                x,
+                y,
+                width,
+                height,
+            } = region;
+            let crop = format!("{}x{}+{}+{}", width, height, x, y);
+            metadata.insert("region".to_string(), crop);
+        }
+        metadata.insert("capture_time".to_string(), template::iso8601(shot.time));
+        metadata.extend(self.metadata.clone());
+        metadata.extend(shot.metadata);
+        FrameInfo {
+            number,
+            dims: (region.width, region.height),
+            sample,
+            output_dir: self.output_dir.clone(),
+            path,
+            metadata,
+        }
+    }
+
+    /// Writes the layers of one frame as an OpenRaster file named like
+    /// any other frame, with `ora` for `{ext}`.
+    fn save_layers<B: ImageData>(
+        &mut self,
+        layers: &[(String, B)],
+        transfer: Transfer,
+        shot: Shot,
+        deadline: Instant,
+    ) {
+        let (dims, sample) = match layers.first() {
+            Some((_, image)) => (image.dims(), image.sample_format()),
+            None => {
+                self.acks.send(Ack::Failed).ok();
+                return;
+            }
+        };
+        let region = self.region(dims);
+        let time = shot.time;
+        let info = self.frame_info(dims, sample, shot, "ora");
+        let mut read = Vec::with_capacity(layers.len());
+        for (name, image) in layers {
+            // Composited premultiplied, so alpha is only resolved when encoding
+            let data = image.read_by(deadline, |data| {
+                format::to_f32(&region.crop(data, dims, sample), sample)
+            });
+            match data {
+                Some(data) => read.push(ora::Layer {
+                    name: name.clone(),
+                    data,
+                }),
+                None => {
+                    let path = info.path;
+                    self.errors.send(ScreenshotError::Timeout { path }).ok();
+                    self.acks.send(Ack::Lost).ok();
+                    return;
+                }
+            }
+        }
+        let start = Instant::now();
+        let result = ora::write(&info.path, info.dims, &read, transfer, time);
+        if result.is_ok() {
+            self.num_images = info.number;
+        }
+        let sidecar = self.sidecar;
+        self.finish(&info, result, start.elapsed(), sidecar);
+    }
+
+    /// Writes `image` through the sink or hands it to the encoding threads,
+    /// reporting failures on the error channel. The frame is acknowledged
+    /// once written, or as lost if the GPU still held the buffer at `deadline`.
+    fn save<B: ImageData>(&mut self, image: &B, mut shot: Shot, deadline: Instant) {
+        if let Some(memory) = shot.memory.take() {
+            let ack = self.save_to_memory(image, memory, deadline);
+            // A frame that is also part of a recording is written as usual
+            if !shot.recorded {
+                self.acks.send(ack).ok();
+                return;
+            }
+        }
+        let (w, h) = image.dims();
+        let region = self.region((w, h));
+        let extension = self.sink.extension().to_string();
+        let info = self.frame_info((w, h), image.sample_format(), shot, &extension);
+        let number = info.number;
+        // The buffer stays locked until the GPU has finished
+        // the frame that copies into it
+        let (alpha, cpu_transfer, sidecar) = (self.alpha, self.cpu_transfer, self.sidecar);
+        let thumbnail = self.thumbnail.map(|thumbnail| region::FrameThumbnail {
+            thumbnail,
+            linear: self.float_transfer == Transfer::Linear,
+        });
+        // Cropped and converted here, so the encoding threads only ever see finished frames
+        let process = |data: &[u8]| {
+            let data = region.crop(data, (w, h), info.sample);
+            prepare(&data, info.sample, alpha, cpu_transfer).into_owned()
+        };
+        let written = match &mut self.pool {
+            // Copied out so the buffer can be reused while the frame is encoded
+            Some(pool) => image.read_by(deadline, |data| {
+                pool.submit(info.clone(), process(data), thumbnail, sidecar);
+                None
+            }),
+            None => {
+                let sink = &mut self.sink;
+                image.read_by(deadline, |data| {
+                    let data = process(data);
+                    let start = Instant::now();
+                    let result = sink.write(&info, &data);
+                    Some((result, start.elapsed(), data))
+                })
+            }
+        };
+        match written {
+            // Later frames are numbered as if this one succeeds
+            Some(None) => {
+                self.num_images = number;
+                let full = self
+                    .pool
+                    .as_ref()
+                    .map_or(false, |pool| pool.in_flight() >= pool.capacity());
+                self.poll_pool(full);
+            }
+            Some(Some((result, elapsed, data))) => {
+                if result.is_ok() {
+                    self.num_images = number;
+                    if let Some(thumbnail) = thumbnail {
+                        let written = thumbnail.write(&info, &data);
+                        self.thumbnail_written(&info, written);
+                    }
+                }
+                self.finish(&info, result, elapsed, sidecar);
+            }
+            None => {
+                let path = info.path;
+                self.errors.send(ScreenshotError::Timeout { path }).ok();
+                self.acks.send(Ack::Lost).ok();
+            }
+        }
+    }
+
+    /// Reports the outcome of writing a frame and writes its sidecar.
+    fn finish(
+        &mut self,
+        info: &FrameInfo,
+        result: io::Result<u64>,
+        encode: Duration,
+        sidecar: bool,
+    ) {
+        let ack = match result {
+            Ok(bytes) => {
+                if sidecar {
+                    let path = info.path.with_extension("json");
+                    if let Err(source) = metadata::write_sidecar(&path, &info.metadata) {
+                        self.errors
+                            .send(ScreenshotError::Write { path, source })
+                            .ok();
+                    }
+                }
+                Ack::Written { encode, bytes }
+            }
+            Err(source) => {
+                let path = info.path.clone();
+                self.errors
+                    .send(ScreenshotError::Write { path, source })
+                    .ok();
+                Ack::Failed
+            }
+        };
+        self.acks.send(ack).ok();
+    }
+
+    /// Reports a thumbnail that could not be written.
+    fn thumbnail_written(&self, info: &FrameInfo, written: io::Result<()>) {
+        if let Err(source) = written {
+            let path = Thumbnail::path(&info.path);
+            self.errors
+                .send(ScreenshotError::Write { path, source })
+                .ok();
+        }
+    }
+
+    /// Starts the encoding threads if more than one is wanted and the sink can be copied.
+    fn start_pool(&mut self) {
+        self.finish_pool();
+        self.pool = None;
+        if self.threads > 1 {
+            self.pool = pool::Pool::new(&*self.sink, self.threads);
+        }
+    }
+
+    /// Keeps `image` for the instant replay, if it is on. The frame is
+    /// compressed on the encoding threads when there are any.
+    fn keep_for_replay<B: ImageData>(&mut self, time: SystemTime, image: &B, deadline: Instant) {
+        let replay = match &mut self.replay {
+            Some(replay) => replay,
+            None => return,
+        };
+        match &mut self.pool {
+            Some(pool) => {
+                if let Some(frame) = replay::Frame::read(time, image, deadline) {
+                    pool.compress(frame);
+                }
+                let full = pool.in_flight() >= pool.capacity();
+                self.poll_pool(full);
+            }
+            None => {
+                replay.push(time, image, deadline);
+            }
+        }
+    }
+
+    /// Reports frames the encoding threads have finished,
+    /// waiting for at least one if `block` is set.
+    fn poll_pool(&mut self, block: bool) {
+        let done = match &mut self.pool {
+            Some(pool) => pool.finished(block),
+            None => return,
+        };
+        self.pool_done(done);
+    }
+
+    /// Waits for every frame on the encoding threads.
+    fn finish_pool(&mut self) {
+        let done = match &mut self.pool {
+            Some(pool) => pool.finish_all(),
+            None => return,
+        };
+        self.pool_done(done);
+    }
+
+    fn pool_done(&mut self, done: Vec<pool::Done<bool>>) {
+        for done in done {
+            match done {
+                pool::Done::Written(done) => {
+                    self.thumbnail_written(&done.info, done.thumbnail);
+                    self.finish(&done.info, done.result, done.elapsed, done.tag);
+                }
+                // Dropped if the replay was turned off meanwhile
+                pool::Done::Compressed(frame) => {
+                    if let Some(replay) = &mut self.replay {
+                        replay.insert(frame);
+                    }
+                }
+            }
+        }
+    }
+}
 
-            // It is vital that ColorType(bit_depth) matches the
-            // type that is used in the screenshot buffer
-            nannou::image::save_buffer(
-                screenshot_path,
-                buf,
-                dims.0 as u32,
-                dims.1 as u32,
-                nannou::image::ColorType::RGBA(8),
-            )
-            .expect("Failed to save image");
-        }
-        if let Ok(buffer) = screenshot_buffer.buffer.read() {
-            self.num_images += 1;
-            let screenshot_path = self
-                .output_dir
-                .join(&format!("screenshot{}.png", self.num_images));
-            write(&(*buffer), screenshot_path, screenshot_buffer.dims);
+/// Resolves alpha, then applies the CPU transfer to float samples,
+/// so the transfer sees linear values.
+fn prepare(
+    data: &[u8],
+    sample: SampleFormat,
+    alpha: AlphaMode,
+    cpu_transfer: Option<Transfer>,
+) -> Cow<'_, [u8]> {
+    let data = alpha.apply(data, sample);
+    match cpu_transfer {
+        Some(transfer) if sample == SampleFormat::F32 => {
+            Cow::Owned(transfer.encode_f32_bytes(&data))
         }
+        _ => data,
     }
 }
 
-fn new_input_image(device: Arc<vk::Device>, dims: [u32; 2]) -> Arc<vk::AttachmentImage> {
+fn new_input_image(
+    device: Arc<vk::Device>,
+    dims: [u32; 2],
+) -> Result<Arc<vk::AttachmentImage>, ScreenshotError> {
     vk::AttachmentImage::with_usage(
         device,
         dims,
         nannou::frame::COLOR_FORMAT,
         vk::ImageUsage {
-            //transfer_source: true,
-            //transfer_destination: true,
+            // Copied between images for offscreen targets
+            transfer_source: true,
+            transfer_destination: true,
             color_attachment: true,
             sampled: true,
             ..vk::ImageUsage::none()
         },
     )
-    .expect("Failed to create input image")
+    .map_err(ScreenshotError::vulkan("Failed to create input image"))
+}
+
+/// An image to keep a copy of a window frame in, with the same samples.
+fn new_frame_copy(
+    device: Arc<vk::Device>,
+    dims: [u32; 2],
+    msaa_samples: u32,
+) -> Result<Arc<vk::AttachmentImage>, ScreenshotError> {
+    vk::AttachmentImage::multisampled_with_usage(
+        device,
+        dims,
+        msaa_samples,
+        nannou::frame::COLOR_FORMAT,
+        vk::ImageUsage {
+            transfer_source: true,
+            transfer_destination: true,
+            ..vk::ImageUsage::none()
+        },
+    )
+    .map_err(ScreenshotError::vulkan("Failed to create frame copy"))
 }
 
-fn new_output_image(device: Arc<vk::Device>, dims: [u32; 2]) -> Arc<vk::AttachmentImage> {
+fn new_output_image(
+    device: Arc<vk::Device>,
+    dims: [u32; 2],
+    sample: SampleFormat,
+) -> Result<Arc<vk::AttachmentImage>, ScreenshotError> {
     vk::AttachmentImage::with_usage(
         device,
         dims,
-        vk::Format::R8G8B8A8Uint,
+        output_image_format(sample),
         vk::ImageUsage {
             transfer_source: true,
             color_attachment: true,
             ..vk::ImageUsage::none()
         },
     )
-    .expect("Failed to create input image")
+    .map_err(ScreenshotError::vulkan("Failed to create output image"))
+}
+
+pub(crate) fn output_image_format(sample: SampleFormat) -> vk::Format {
+    match sample {
+        SampleFormat::U8 => vk::Format::R8G8B8A8Uint,
+        SampleFormat::U16 => vk::Format::R16G16B16A16Uint,
+        SampleFormat::F32 => vk::Format::R32G32B32A32Sfloat,
+    }
 }
 
 fn new_screenshot_buffer(
     device: Arc<vk::Device>,
     dims: (usize, usize),
-) -> Arc<vk::CpuAccessibleBuffer<[[u8; NUM_COLOURS]]>> {
-    let buf = vec![[0u8; NUM_COLOURS]; dims.0 * dims.1];
-    vk::CpuAccessibleBuffer::from_iter(
-        device.clone(),
-        vk::BufferUsage {
-            transfer_destination: true,
-            ..vk::BufferUsage::none()
-        },
-        buf.into_iter(),
-    )
-    .expect("Failed to create screenshot buffer")
+    sample: SampleFormat,
+) -> Result<PixelBuffer, ScreenshotError> {
+    fn new_buffer<T>(
+        device: Arc<vk::Device>,
+        len: usize,
+    ) -> Result<Arc<vk::CpuAccessibleBuffer<[T]>>, ScreenshotError>
+    where
+        T: Default + Clone + Send + Sync + 'static,
+    {
+        let buf = vec![T::default(); len];
+        vk::CpuAccessibleBuffer::from_iter(
+            device,
+            vk::BufferUsage {
+                transfer_destination: true,
+                ..vk::BufferUsage::none()
+            },
+            buf.into_iter(),
+        )
+        .map_err(ScreenshotError::vulkan(
+            "Failed to create screenshot buffer",
+        ))
+    }
+    let len = dims.0 * dims.1;
+    Ok(match sample {
+        SampleFormat::U8 => PixelBuffer::U8(new_buffer(device, len)?),
+        SampleFormat::U16 => PixelBuffer::U16(new_buffer(device, len)?),
+        SampleFormat::F32 => PixelBuffer::F32(new_buffer(device, len)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Pixels in memory, standing in for a GPU buffer.
+    struct Image {
+        dims: (usize, usize),
+        data: Vec<u8>,
+        /// Never released, like a buffer the GPU is stuck on.
+        held: bool,
+    }
+
+    impl ImageData for Image {
+        fn dims(&self) -> (usize, usize) {
+            self.dims
+        }
+
+        fn sample_format(&self) -> SampleFormat {
+            SampleFormat::U8
+        }
+
+        fn read<R, F: FnOnce(&[u8]) -> R>(&self, f: F) -> Option<R> {
+            if self.held {
+                None
+            } else {
+                Some(f(&self.data))
+            }
+        }
+    }
+
+    fn image(dims: (usize, usize), value: u8) -> Image {
+        Image {
+            dims,
+            data: vec![value; dims.0 * dims.1 * 4],
+            held: false,
+        }
+    }
+
+    fn writer<S: FrameSink + 'static>(
+        sink: S,
+        errors: Sender<ScreenshotError>,
+        acks: Sender<Ack>,
+    ) -> ShotWriter {
+        let output_dir = std::env::temp_dir().join("screenshot-tests-nonexistent");
+        let mut writer = ShotWriter {
+            num_images: 0,
+            output_dir: output_dir.clone(),
+            sink: Box::new(sink),
+            template: FilenameTemplate::default(),
+            vars: HashMap::new(),
+            policy: OutputPolicy::default(),
+            metadata: Metadata::new(),
+            sidecar: false,
+            cpu_transfer: None,
+            float_transfer: Transfer::Linear,
+            alpha: AlphaMode::default(),
+            region: None,
+            thumbnail: None,
+            replay: None,
+            threads: 1,
+            pool: None,
+            errors,
+            acks,
+        };
+        writer.enter_dir(output_dir);
+        writer
+    }
+
+    /// Runs the saving thread loop on `images` and returns what it acknowledged.
+    fn run<S: FrameSink + 'static>(sink: S, images: Vec<Image>) -> (Vec<Ack>, usize) {
+        let (error_out, errors) = mpsc::channel();
+        let (ack_out, acks) = mpsc::channel();
+        let (save_out, returned) = mpsc::channel();
+        let (images_out, images_in) = mpsc::channel();
+        let writer = writer(sink, error_out, ack_out);
+        let num_images = images.len();
+        images_out
+            .send(Envelope::Attach(0, writer, save_out))
+            .unwrap();
+        for image in images {
+            let shot = Shot::new(Metadata::new());
+            images_out
+                .send(Envelope::To(0, Msg::Buffer(image, shot)))
+                .unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_millis(20);
+        images_out
+            .send(Envelope::To(0, Msg::Kill(deadline)))
+            .unwrap();
+        drop(images_out);
+        save_images(images_in);
+        // Every buffer goes back to be captured into again
+        assert_eq!(returned.try_iter().count(), num_images);
+        (acks.try_iter().collect(), errors.try_iter().count())
+    }
+
+    #[test]
+    fn sink_receives_frames_in_order() {
+        let (frame_out, frames) = mpsc::channel();
+        let sink = move |info: &FrameInfo, data: &[u8]| {
+            frame_out
+                .send((info.number, info.dims, data.to_vec()))
+                .unwrap();
+            Ok(())
+        };
+        let images = vec![image((2, 1), 10), image((2, 1), 20), image((3, 2), 30)];
+        let (acks, errors) = run(sink, images);
+        let frames: Vec<_> = frames.try_iter().collect();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0], (1, (2, 1), vec![10; 8]));
+        assert_eq!(frames[1], (2, (2, 1), vec![20; 8]));
+        assert_eq!(frames[2], (3, (3, 2), vec![30; 24]));
+        assert_eq!(acks.len(), 3);
+        assert!(acks.iter().all(|ack| match ack {
+            Ack::Written { bytes: 0, .. } => true,
+            _ => false,
+        }));
+        assert_eq!(errors, 0);
+    }
+
+    /// Reports the frame size as written and a fixed trailer on flush.
+    struct TrailerSink;
+
+    impl FrameSink for TrailerSink {
+        fn write(&mut self, _: &FrameInfo, data: &[u8]) -> io::Result<u64> {
+            Ok(data.len() as u64)
+        }
+
+        fn flush(&mut self) -> io::Result<u64> {
+            Ok(7)
+        }
+    }
+
+    #[test]
+    fn acks_carry_the_bytes_the_sink_reports() {
+        let (acks, errors) = run(TrailerSink, vec![image((2, 1), 0), image((3, 1), 0)]);
+        let bytes: Vec<_> = acks
+            .iter()
+            .map(|ack| match *ack {
+                Ack::Written { bytes, .. } => (bytes, true),
+                Ack::Flushed(bytes) => (bytes, false),
+                _ => panic!("frame was not written"),
+            })
+            .collect();
+        assert_eq!(bytes, vec![(8, true), (12, true), (7, false)]);
+        assert_eq!(errors, 0);
+    }
+
+    #[test]
+    fn flushing_the_queue_keeps_the_stream_open() {
+        /// Counts the frames written before each sink flush.
+        struct Streams(Sender<usize>, usize);
+
+        impl FrameSink for Streams {
+            fn write(&mut self, _: &FrameInfo, _: &[u8]) -> io::Result<u64> {
+                self.1 += 1;
+                Ok(0)
+            }
+
+            fn flush(&mut self) -> io::Result<u64> {
+                self.0.send(mem::replace(&mut self.1, 0)).ok();
+                Ok(0)
+            }
+        }
+
+        let (stream_out, streams) = mpsc::channel();
+        let (error_out, _errors) = mpsc::channel();
+        let (ack_out, _acks) = mpsc::channel();
+        let (save_out, _returned) = mpsc::channel();
+        let (images_out, images_in) = mpsc::channel();
+        let writer = writer(Streams(stream_out, 0), error_out, ack_out);
+        images_out
+            .send(Envelope::Attach(0, writer, save_out))
+            .unwrap();
+        // A shot, then a recording starting before the shot is flushed
+        let recording = std::env::temp_dir().join("screenshot-tests-nonexistent");
+        let messages = vec![
+            Msg::Buffer(image((1, 1), 0), Shot::new(Metadata::new())),
+            Msg::ChangeDir(recording),
+            Msg::Buffer(image((1, 1), 0), Shot::new(Metadata::new())),
+            Msg::Flush,
+            Msg::Buffer(image((1, 1), 0), Shot::new(Metadata::new())),
+            Msg::Kill(Instant::now() + Duration::from_millis(20)),
+        ];
+        for msg in messages {
+            images_out.send(Envelope::To(0, msg)).unwrap();
+        }
+        drop(images_out);
+        save_images(images_in);
+        assert_eq!(streams.try_iter().collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn replay_frames_are_compressed_on_the_encoding_threads() {
+        /// Sends the number and first byte of each frame, from any thread.
+        #[derive(Clone)]
+        struct Frames(Sender<(usize, u8)>);
+
+        impl FrameSink for Frames {
+            fn write(&mut self, info: &FrameInfo, data: &[u8]) -> io::Result<u64> {
+                self.0.send((info.number, data[0])).ok();
+                Ok(0)
+            }
+
+            fn try_clone(&self) -> Option<Box<dyn FrameSink>> {
+                Some(Box::new(self.clone()))
+            }
+        }
+
+        let (frame_out, frames) = mpsc::channel();
+        let (error_out, errors) = mpsc::channel();
+        let (ack_out, acks) = mpsc::channel();
+        let (save_out, returned) = mpsc::channel();
+        let (images_out, images_in) = mpsc::channel();
+        let writer = writer(Frames(frame_out), error_out, ack_out);
+        images_out
+            .send(Envelope::Attach(0, writer, save_out))
+            .unwrap();
+        let dir = std::env::temp_dir().join("screenshot-tests-nonexistent");
+        let mut messages = vec![
+            Msg::Threads(2),
+            Msg::ReplayLength(Some(Duration::from_secs(60))),
+        ];
+        for value in 1..=4 {
+            messages.push(Msg::Replay(image((1, 1), value), SystemTime::now()));
+        }
+        messages.push(Msg::SaveReplay(dir));
+        messages.push(Msg::Kill(Instant::now() + Duration::from_secs(1)));
+        for msg in messages {
+            images_out.send(Envelope::To(0, msg)).unwrap();
+        }
+        drop(images_out);
+        save_images(images_in);
+        let mut frames: Vec<_> = frames.try_iter().collect();
+        frames.sort();
+        assert_eq!(frames, vec![(1, 1), (2, 2), (3, 3), (4, 4)]);
+        let acks: Vec<_> = acks.try_iter().collect();
+        assert!(match acks[0] {
+            Ack::Queued(4) => true,
+            _ => false,
+        });
+        assert_eq!(acks.len(), 5);
+        assert_eq!(returned.try_iter().count(), 4);
+        assert_eq!(errors.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn offscreen_buffers_are_not_returned() {
+        let (error_out, _errors) = mpsc::channel();
+        let (ack_out, acks) = mpsc::channel();
+        let (save_out, returned) = mpsc::channel();
+        let (images_out, images_in) = mpsc::channel();
+        let sink = |_: &FrameInfo, _: &[u8]| Ok(());
+        images_out
+            .send(Envelope::Attach(
+                0,
+                writer(sink, error_out, ack_out),
+                save_out,
+            ))
+            .unwrap();
+        let messages = vec![
+            Msg::Buffer(image((1, 1), 0), Shot::new(Metadata::new())),
+            Msg::Offscreen(image((8, 8), 0), Shot::new(Metadata::new())),
+            Msg::Kill(Instant::now() + Duration::from_millis(20)),
+        ];
+        for msg in messages {
+            images_out.send(Envelope::To(0, msg)).unwrap();
+        }
+        drop(images_out);
+        save_images(images_in);
+        let returned: Vec<_> = returned.try_iter().map(|image| image.dims).collect();
+        assert_eq!(returned, vec![(1, 1)]);
+        assert_eq!(acks.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn queued_frames_keep_the_policy_they_were_taken_with() {
+        let dir = std::env::temp_dir().join(format!("screenshot-policy-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("poster.png"), b"").unwrap();
+        let (path_out, paths) = mpsc::channel();
+        let sink = move |info: &FrameInfo, _: &[u8]| {
+            path_out.send(info.path.clone()).unwrap();
+            Ok(())
+        };
+        let (error_out, _errors) = mpsc::channel();
+        let (ack_out, _acks) = mpsc::channel();
+        let (save_out, _returned) = mpsc::channel();
+        let (images_out, images_in) = mpsc::channel();
+        images_out
+            .send(Envelope::Attach(
+                0,
+                writer(sink, error_out, ack_out),
+                save_out,
+            ))
+            .unwrap();
+        let messages = vec![
+            Msg::Template(FilenameTemplate::parse("poster.{ext}").unwrap()),
+            Msg::ChangeDir(dir.clone()),
+            Msg::Buffer(image((1, 1), 0), Shot::new(Metadata::new())),
+            Msg::Policy(OutputPolicy::Overwrite),
+            Msg::Buffer(image((1, 1), 0), Shot::new(Metadata::new())),
+            Msg::Kill(Instant::now() + Duration::from_millis(20)),
+        ];
+        for msg in messages {
+            images_out.send(Envelope::To(0, msg)).unwrap();
+        }
+        drop(images_out);
+        save_images(images_in);
+        let paths: Vec<_> = paths.try_iter().collect();
+        assert_eq!(
+            paths,
+            vec![dir.join("poster-2.png"), dir.join("poster.png")]
+        );
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn alpha_is_resolved_before_the_transfer() {
+        let to_bytes = |samples: &[f32]| -> Vec<u8> {
+            samples
+                .iter()
+                .flat_map(|s| s.to_ne_bytes().to_vec())
+                .collect()
+        };
+        let data = to_bytes(&[0.25, 0.25, 0.25, 0.5]);
+        let prepared = prepare(
+            &data,
+            SampleFormat::F32,
+            AlphaMode::Straight,
+            Some(Transfer::Srgb),
+        );
+        // Straight 0.5 encoded, where the other order would divide 0.537 by 0.5
+        let encoded = Transfer::Srgb.encode(0.5);
+        assert_eq!(&*prepared, &to_bytes(&[encoded, encoded, encoded, 0.5])[..]);
+    }
+
+    #[test]
+    fn sink_errors_are_acknowledged_as_failed() {
+        let sink = |_: &FrameInfo, _: &[u8]| Err(io::Error::new(io::ErrorKind::Other, "full"));
+        let (acks, errors) = run(sink, vec![image((1, 1), 0)]);
+        assert_eq!(acks.len(), 1);
+        assert!(match acks[0] {
+            Ack::Failed => true,
+            _ => false,
+        });
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    fn held_buffers_are_acknowledged_as_lost() {
+        let sink = |_: &FrameInfo, _: &[u8]| -> io::Result<()> { panic!("frame was written") };
+        let held = Image {
+            held: true,
+            ..image((1, 1), 0)
+        };
+        let (acks, errors) = run(sink, vec![held]);
+        assert_eq!(acks.len(), 1);
+        assert!(match acks[0] {
+            Ack::Lost => true,
+            _ => false,
+        });
+        assert_eq!(errors, 1);
+    }
+
+    fn next_path(writer: &mut ShotWriter) -> PathBuf {
+        let shot = Shot::new(Metadata::new());
+        writer
+            .frame_info((1, 1), SampleFormat::U8, shot, "png")
+            .path
+    }
+
+    #[test]
+    fn taken_names_without_frame_get_a_suffix() {
+        let dir = std::env::temp_dir().join(format!("screenshot-tests-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("poster.png"), b"").unwrap();
+        let (error_out, _errors) = mpsc::channel();
+        let (ack_out, _acks) = mpsc::channel();
+        let sink = |_: &FrameInfo, _: &[u8]| Ok(());
+        let mut writer = writer(sink, error_out, ack_out);
+        writer.template = FilenameTemplate::parse("poster.{ext}").unwrap();
+        writer.enter_dir(dir.clone());
+        assert_eq!(next_path(&mut writer), dir.join("poster-2.png"));
+        fs::write(dir.join("poster-2.png"), b"").unwrap();
+        assert_eq!(next_path(&mut writer), dir.join("poster-3.png"));
+        writer.policy = OutputPolicy::Overwrite;
+        assert_eq!(next_path(&mut writer), dir.join("poster.png"));
+        fs::remove_dir_all(&dir).ok();
+    }
 }
@@ -0,0 +1,293 @@
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// Key/value text stored with a saved frame, such as a seed or parameters.
+///
+/// `Shots` fills in `sketch`, `crate_version`, `git_revision`, `window_size`
+/// and `capture_time`, then adds the values from `Shots::set_metadata`
+/// and `Shots::take_with_metadata`, later ones winning.
+pub type Metadata = BTreeMap<String, String>;
+
+/// Version of the sketch crate in `{dir}/Cargo.toml`, if it can be found.
+pub(crate) fn crate_version(dir: &Path) -> Option<String> {
+    let manifest = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    let mut in_package = false;
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_package = line == "[package]";
+            continue;
+        }
+        let mut split = line.splitn(2, '=');
+        if in_package && split.next().map(str::trim) == Some("version") {
+            let value = split.next()?.trim();
+            return Some(value.trim_matches('"').to_string());
+        }
+    }
+    None
+}
+
+lazy_static! {
+    /// Revisions already looked up, by directory.
+    static ref REVISIONS: Mutex<HashMap<PathBuf, Option<String>>> = Mutex::new(HashMap::new());
+}
+
+/// Commit checked out in `dir`, with `-dirty` appended if it has
+/// uncommitted changes. `None` outside a repository or without git.
+///
+/// Git runs once per directory, the first time it is asked for,
+/// so every window of a sketch gets the same answer without waiting.
+pub(crate) fn git_revision(dir: &Path) -> Option<String> {
+    let mut revisions = REVISIONS.lock().unwrap_or_else(|e| e.into_inner());
+    revisions
+        .entry(dir.to_path_buf())
+        .or_insert_with(|| query_git_revision(dir))
+        .clone()
+}
+
+fn query_git_revision(dir: &Path) -> Option<String> {
+    let git = |args: &[&str]| {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .ok()?;
+        if output.status.success() {
+            String::from_utf8(output.stdout).ok()
+        } else {
+            None
+        }
+    };
+    let revision = git(&["rev-parse", "HEAD"])?.trim().to_string();
+    match git(&["status", "--porcelain", "--untracked-files=no"]) {
+        Some(status) if !status.trim().is_empty() => Some(revision + "-dirty"),
+        _ => Some(revision),
+    }
+}
+
+/// Whether `key` can be stored as a PNG text keyword:
+/// 1 to 79 printable ASCII characters without surrounding spaces.
+pub(crate) fn valid_key(key: &str) -> bool {
+    (1..=79).contains(&key.len())
+        && key.trim() == key
+        && key.chars().all(|c| (' '..='~').contains(&c))
+}
+
+/// PNG text chunks for `metadata`, `tEXt` for printable Latin-1 and `iTXt` otherwise.
+pub(crate) fn png_chunks(metadata: &Metadata) -> io::Result<Vec<([u8; 4], Vec<u8>)>> {
+    metadata
+        .iter()
+        .map(|(key, value)| {
+            if !valid_key(key) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} is not a valid PNG text keyword", key),
+                ));
+            }
+            let mut data = key.as_bytes().to_vec();
+            data.push(0);
+            if latin1_printable(value) {
+                data.extend(value.chars().map(|c| c as u8));
+                Ok((*b"tEXt", data))
+            } else {
+                // Uncompressed, with empty language tag and translated keyword
+                data.extend_from_slice(&[0, 0, 0, 0]);
+                data.extend_from_slice(value.as_bytes());
+                Ok((*b"iTXt", data))
+            }
+        })
+        .collect()
+}
+
+/// Writes `metadata` as a flat JSON object to `path`.
+pub(crate) fn write_sidecar(path: &Path, metadata: &Metadata) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut out, metadata)?;
+    out.write_all(b"\n")?;
+    out.flush()
+}
+
+/// Reads the `tEXt`, `zTXt` and `iTXt` chunks of the PNG file at `path`.
+pub(crate) fn read_png_text(path: &Path) -> io::Result<Metadata> {
+    let png = fs::read(path)?;
+    if !png.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a PNG file"));
+    }
+    let mut metadata = Metadata::new();
+    let mut rest = &png[8..];
+    // Files cut off anywhere before IEND are truncated
+    loop {
+        let len = match rest.get(..4) {
+            Some(len) => u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize,
+            None => 0,
+        };
+        if rest.len() < 12 + len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated PNG chunk",
+            ));
+        }
+        let name = &rest[4..8];
+        let data = &rest[8..8 + len];
+        if name == b"IEND" {
+            break;
+        }
+        let mut split = data.splitn(2, |&b| b == 0);
+        let key = split.next().unwrap_or_default();
+        let body = split.next().unwrap_or_default();
+        let value = match name {
+            b"tEXt" => Some(latin1(body)),
+            b"zTXt" => body
+                .get(1..)
+                .and_then(|z| inflate::inflate_bytes_zlib(z).ok())
+                .map(|text| latin1(&text)),
+            b"iTXt" => itxt(body),
+            _ => None,
+        };
+        if let Some(value) = value {
+            metadata.insert(latin1(key), value);
+        }
+        rest = &rest[12 + len..];
+    }
+    Ok(metadata)
+}
+
+/// Reads a JSON sidecar written by `write_sidecar`.
+pub(crate) fn read_sidecar(path: &Path) -> io::Result<Metadata> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(io::BufReader::new(file))?)
+}
+
+/// Whether `value` fits a `tEXt` chunk: printable Latin-1 only,
+/// without NUL, line breaks or other control characters.
+fn latin1_printable(value: &str) -> bool {
+    value
+        .chars()
+        .all(|c| (' '..='~').contains(&c) || ('\u{a1}'..='\u{ff}').contains(&c))
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+/// The text of an `iTXt` chunk after its keyword.
+fn itxt(body: &[u8]) -> Option<String> {
+    let (compressed, rest) = (*body.get(0)? == 1, body.get(2..)?);
+    // Skip the language tag and translated keyword
+    let mut split = rest.splitn(3, |&b| b == 0);
+    let text = split.nth(2)?;
+    let text = if compressed {
+        inflate::inflate_bytes_zlib(text).ok()?
+    } else {
+        text.to_vec()
+    };
+    String::from_utf8(text).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{self, OutputFormat, SampleFormat};
+    use std::process;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("screenshot-{}-{}", process::id(), name))
+    }
+
+    fn metadata(pairs: &[(&str, &str)]) -> Metadata {
+        pairs
+            .iter()
+            .map(|&(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn text_chunk_types() {
+        let chunks = png_chunks(&metadata(&[
+            ("ascii", "seed 42"),
+            ("latin1", "café"),
+            ("newline", "a\nb"),
+            ("nul", "a\0b"),
+            ("unicode", "日本"),
+        ]))
+        .unwrap();
+        let types: Vec<_> = chunks.iter().map(|(name, _)| name).collect();
+        assert_eq!(types, [b"tEXt", b"tEXt", b"iTXt", b"iTXt", b"iTXt"]);
+        // Latin-1, not UTF-8
+        assert_eq!(chunks[1].1, b"latin1\0caf\xe9");
+    }
+
+    #[test]
+    fn invalid_keys_are_refused() {
+        assert!(png_chunks(&metadata(&[(" seed", "1")])).is_err());
+        assert!(png_chunks(&metadata(&[("", "1")])).is_err());
+        assert!(png_chunks(&metadata(&[(&"k".repeat(80), "1")])).is_err());
+    }
+
+    #[test]
+    fn png_text_round_trips() {
+        let stored = metadata(&[
+            ("ascii", "seed 42"),
+            ("empty", ""),
+            ("latin1", "café ½"),
+            ("lines", "one\ntwo\r\n"),
+            ("unicode", "日本 🎨"),
+        ]);
+        let path = temp_path("text.png");
+        format::write_image_with_metadata(
+            &path,
+            &[0; 4],
+            (1, 1),
+            SampleFormat::U8,
+            OutputFormat::Png,
+            &stored,
+        )
+        .unwrap();
+        let read = read_png_text(&path);
+        fs::remove_file(&path).ok();
+        assert_eq!(read.unwrap(), stored);
+    }
+
+    #[test]
+    fn truncated_chunks_are_an_error() {
+        let stored = metadata(&[("seed", "42")]);
+        let path = temp_path("truncated.png");
+        format::write_image_with_metadata(
+            &path,
+            &[0; 4],
+            (1, 1),
+            SampleFormat::U8,
+            OutputFormat::Png,
+            &stored,
+        )
+        .unwrap();
+        let png = fs::read(&path).unwrap();
+        // Cut into the text chunk right after the header
+        let text = png.windows(4).position(|w| w == b"tEXt").unwrap();
+        fs::write(&path, &png[..text + 6]).unwrap();
+        let truncated = read_png_text(&path);
+        // Or between chunks, before IEND
+        fs::write(&path, &png[..text - 4]).unwrap();
+        let no_end = read_png_text(&path);
+        fs::write(&path, b"GIF89a").unwrap();
+        let not_png = read_png_text(&path);
+        fs::remove_file(&path).ok();
+        assert_eq!(truncated.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(no_end.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(not_png.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn sidecar_round_trips() {
+        let stored = metadata(&[("quote", "\"a\" \\ b"), ("unicode", "日本"), ("seed", "42")]);
+        let path = temp_path("sidecar.json");
+        write_sidecar(&path, &stored).unwrap();
+        let read = read_sidecar(&path);
+        fs::remove_file(&path).ok();
+        assert_eq!(read.unwrap(), stored);
+    }
+}
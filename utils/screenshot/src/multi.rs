@@ -0,0 +1,159 @@
+use super::template;
+use super::{spawn_saving_thread, Buffer, Envelope, FlushReport, Shots};
+use super::{FrameSink, PngSink, ScreenshotError};
+use nannou::prelude::*;
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Captures several windows, such as a control window and a projector
+/// window, sharing one saving thread.
+///
+/// Every window gets its own `Shots`, writing into `{basedir}/dist/{name}`.
+///
+/// ```no_run
+/// # use nannou::prelude::*;
+/// # use screenshot::{MultiShots, ScreenshotError};
+/// # fn setup(app: &App, control_id: WindowId, projector_id: WindowId, frame: &Frame)
+/// #     -> Result<(), ScreenshotError> {
+/// let mut shots = MultiShots::new(env!("CARGO_MANIFEST_DIR"));
+/// shots.add_window(app, control_id, "control")?;
+/// shots.add_window(app, projector_id, "projector")?;
+/// shots.capture(frame)?; // in the view function of either window
+/// shots.take(); // save both windows
+/// # Ok(())
+/// # }
+/// ```
+pub struct MultiShots {
+    basedir: String,
+    images_out: Sender<Envelope<Buffer>>,
+    saving_thread: Option<JoinHandle<()>>,
+    windows: HashMap<WindowId, Shots>,
+    next_id: usize,
+}
+
+impl MultiShots {
+    pub fn new(basedir: &str) -> Self {
+        let (images_out, saving_thread) = spawn_saving_thread();
+        MultiShots {
+            basedir: basedir.to_string(),
+            images_out,
+            saving_thread: Some(saving_thread),
+            windows: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Starts capturing `window_id` into the `name` subdirectory,
+    /// which is also available to filename templates as `{window}`.
+    /// Path separators and other characters a file name can't hold
+    /// are replaced in `name` like in template values.
+    pub fn add_window(
+        &mut self,
+        app: &App,
+        window_id: WindowId,
+        name: &str,
+    ) -> Result<&mut Shots, ScreenshotError> {
+        self.add_window_with_sink(app, window_id, name, PngSink)
+    }
+
+    /// Same as `MultiShots::add_window` but hands frames to `sink`.
+    pub fn add_window_with_sink<S>(
+        &mut self,
+        app: &App,
+        window_id: WindowId,
+        name: &str,
+        sink: S,
+    ) -> Result<&mut Shots, ScreenshotError>
+    where
+        S: FrameSink + 'static,
+    {
+        // A window added again replaces its old capture
+        if let Some(old) = self.windows.remove(&window_id) {
+            old.flush(Duration::from_secs(1));
+        }
+        let images_out = self.images_out.clone();
+        let mut shots = Shots::attach(
+            app,
+            window_id,
+            &self.basedir,
+            sink,
+            images_out,
+            self.next_id,
+        )?;
+        self.next_id += 1;
+        let name = template::sanitize(name);
+        shots.output_dir(&name)?;
+        shots.set_var("window", &name)?;
+        Ok(self.windows.entry(window_id).or_insert(shots))
+    }
+
+    /// The capture of `window_id`, for settings that differ per window.
+    pub fn get(&self, window_id: WindowId) -> Option<&Shots> {
+        self.windows.get(&window_id)
+    }
+
+    pub fn get_mut(&mut self, window_id: WindowId) -> Option<&mut Shots> {
+        self.windows.get_mut(&window_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (WindowId, &Shots)> {
+        self.windows.iter().map(|(id, shots)| (*id, shots))
+    }
+
+    /// Passes `frame` to the `Shots` of the window it belongs to.
+    pub fn capture(&self, frame: &Frame) -> Result<(), ScreenshotError> {
+        match self.windows.get(&frame.window_id()) {
+            Some(shots) => shots.capture(frame),
+            None => Ok(()),
+        }
+    }
+
+    /// Takes a shot of every window on its next frame.
+    pub fn take(&self) {
+        for shots in self.windows.values() {
+            shots.take();
+        }
+    }
+
+    pub fn start_recording(&self) -> Result<(), ScreenshotError> {
+        self.windows.values().map(Shots::start_recording).collect()
+    }
+
+    pub fn stop_recording(&self) {
+        for shots in self.windows.values() {
+            shots.stop_recording();
+        }
+    }
+
+    /// Errors of every window since the last call.
+    pub fn errors(&self) -> impl Iterator<Item = ScreenshotError> + '_ {
+        self.windows.values().flat_map(Shots::errors)
+    }
+
+    /// Waits for every window as in `Shots::flush`, sharing one `timeout`,
+    /// and adds up their reports.
+    pub fn flush(mut self, timeout: Duration) -> FlushReport {
+        let deadline = Instant::now() + timeout;
+        let mut report = FlushReport {
+            completed: true,
+            ..FlushReport::default()
+        };
+        for (_, shots) in self.windows.drain() {
+            let window = shots.flush(deadline.saturating_duration_since(Instant::now()));
+            report.written += window.written;
+            report.lost += window.lost;
+            report.failed += window.failed;
+            report.completed &= window.completed;
+        }
+        // A thread stuck in a slow sink is left to finish on its own
+        if report.completed {
+            if let Some(saving_thread) = self.saving_thread.take() {
+                drop(self.images_out);
+                saving_thread.join().ok();
+            }
+        }
+        report
+    }
+}
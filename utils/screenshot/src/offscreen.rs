@@ -0,0 +1,86 @@
+/// A frame sized piece of an offscreen target, see `Shots::capture_offscreen`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Piece {
+    /// Top left corner in the target, in pixels.
+    pub(crate) offset: [u32; 2],
+    /// Pixels of the frame that fall inside the target.
+    pub(crate) extent: [u32; 2],
+    /// Centre of the frame in target coordinates: one unit per pixel,
+    /// origin in the middle and y pointing up.
+    pub(crate) center: (f32, f32),
+}
+
+/// Splits a target of `dims` into pieces of `frame` size, row by row from the top left.
+pub(crate) fn pieces(dims: (usize, usize), frame: (usize, usize)) -> Vec<Piece> {
+    let (fw, fh) = (frame.0.max(1), frame.1.max(1));
+    let cols = (dims.0 + fw - 1) / fw;
+    let rows = (dims.1 + fh - 1) / fh;
+    let mut pieces = Vec::with_capacity(cols * rows);
+    for row in 0..rows {
+        for col in 0..cols {
+            let (x, y) = (col * fw, row * fh);
+            pieces.push(Piece {
+                offset: [x as u32, y as u32],
+                extent: [fw.min(dims.0 - x) as u32, fh.min(dims.1 - y) as u32],
+                center: (
+                    (x * 2 + fw) as f32 / 2.0 - dims.0 as f32 / 2.0,
+                    dims.1 as f32 / 2.0 - (y * 2 + fh) as f32 / 2.0,
+                ),
+            });
+        }
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_that_divide_the_target() {
+        let pieces = pieces((4, 2), (2, 2));
+        assert_eq!(
+            pieces,
+            [
+                Piece {
+                    offset: [0, 0],
+                    extent: [2, 2],
+                    center: (-1.0, 0.0),
+                },
+                Piece {
+                    offset: [2, 0],
+                    extent: [2, 2],
+                    center: (1.0, 0.0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn edge_pieces_are_cut_to_the_target() {
+        let pieces = pieces((5, 3), (2, 2));
+        assert_eq!(pieces.len(), 6);
+        let offsets: Vec<_> = pieces.iter().map(|piece| piece.offset).collect();
+        assert_eq!(offsets, [[0, 0], [2, 0], [4, 0], [0, 2], [2, 2], [4, 2]]);
+        let extents: Vec<_> = pieces.iter().map(|piece| piece.extent).collect();
+        assert_eq!(extents, [[2, 2], [2, 2], [1, 2], [2, 1], [2, 1], [1, 1]]);
+        // Centred on the whole frame, which hangs over the edge of the target
+        assert_eq!(pieces[2].center, (2.5, 0.5));
+        assert_eq!(pieces[5].center, (2.5, -1.5));
+    }
+
+    #[test]
+    fn frames_larger_than_the_target() {
+        let pieces = pieces((3, 1), (4, 4));
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(pieces[0].extent, [3, 1]);
+        assert_eq!(pieces[0].center, (0.5, -1.5));
+    }
+
+    #[test]
+    fn empty_sizes() {
+        assert!(pieces((0, 0), (2, 2)).is_empty());
+        // An empty frame is taken as a single pixel
+        assert_eq!(pieces((2, 1), (0, 0)).len(), 2);
+    }
+}
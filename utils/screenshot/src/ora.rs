@@ -0,0 +1,376 @@
+use super::animation::crc32;
+use super::color::Transfer;
+use super::format::{self, SampleFormat};
+use super::metadata::Metadata;
+use super::region::Thumbnail;
+use super::template;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// A layer read back for an OpenRaster file, as linear premultiplied float RGBA.
+pub(crate) struct Layer {
+    pub(crate) name: String,
+    pub(crate) data: Vec<f32>,
+}
+
+/// Writes `layers`, bottom first, to `path` as an OpenRaster zip:
+/// one PNG per layer, `stack.xml`, the merged image and a thumbnail,
+/// each encoded with `transfer`. Returns the size of the file.
+pub(crate) fn write(
+    path: &Path,
+    dims: (usize, usize),
+    layers: &[Layer],
+    transfer: Transfer,
+    time: SystemTime,
+) -> io::Result<u64> {
+    let mut zip = Zip::new(BufWriter::new(File::create(path)?), time);
+    // Readers identify the format by this first, uncompressed entry
+    zip.add("mimetype", b"image/openraster")?;
+    zip.add("stack.xml", stack_xml(dims, layers).as_bytes())?;
+    for (i, layer) in layers.iter().enumerate() {
+        let png = format::encode_png(&encode(&layer.data, transfer), dims, &Metadata::new())?;
+        zip.add(&format!("data/layer{}.png", i), &png)?;
+    }
+    let merged = encode(&merge(dims, layers), transfer);
+    zip.add(
+        "mergedimage.png",
+        &format::encode_png(&merged, dims, &Metadata::new())?,
+    )?;
+    let (thumbnail, thumbnail_dims) =
+        Thumbnail::new(256, 256).shrink(&merged, dims, SampleFormat::U8)?;
+    let thumbnail = format::encode_png(&thumbnail, thumbnail_dims, &Metadata::new())?;
+    zip.add("Thumbnails/thumbnail.png", &thumbnail)?;
+    zip.finish()
+}
+
+/// Un-premultiplies linear RGBA, as OpenRaster expects straight
+/// alpha, then encodes the colour with `transfer` to 8 bit.
+fn encode(data: &[f32], transfer: Transfer) -> Vec<u8> {
+    let to_u8 = |v: f32| (v.max(0.0).min(1.0) * 255.0).round() as u8;
+    let mut encoded = Vec::with_capacity(data.len());
+    for pixel in data.chunks_exact(4) {
+        let a = pixel[3].max(0.0).min(1.0);
+        for &c in &pixel[..3] {
+            let straight = if a > 0.0 { c / a } else { 0.0 };
+            encoded.push(to_u8(transfer.encode(straight)));
+        }
+        encoded.push(to_u8(a));
+    }
+    encoded
+}
+
+/// The stack lists the topmost layer first.
+fn stack_xml(dims: (usize, usize), layers: &[Layer]) -> String {
+    let mut xml = String::from("<?xml version='1.0' encoding='UTF-8'?>\n");
+    xml += &format!(
+        "<image version=\"0.0.5\" w=\"{}\" h=\"{}\">\n<stack>\n",
+        dims.0, dims.1
+    );
+    for (i, layer) in layers.iter().enumerate().rev() {
+        xml += &format!(
+            "<layer name=\"{}\" src=\"data/layer{}.png\" x=\"0\" y=\"0\" \
+             opacity=\"1.0\" visibility=\"visible\" composite-op=\"svg:src-over\"/>\n",
+            escape(&layer.name),
+            i
+        );
+    }
+    xml += "</stack>\n</image>\n";
+    xml
+}
+
+/// Escapes text for XML and HTML attributes and content.
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Composites the layers bottom to top with premultiplied source over.
+fn merge(dims: (usize, usize), layers: &[Layer]) -> Vec<f32> {
+    let mut merged = vec![0.0f32; dims.0 * dims.1 * 4];
+    for layer in layers {
+        for (dst, src) in merged.chunks_exact_mut(4).zip(layer.data.chunks_exact(4)) {
+            let inverse = 1.0 - src[3];
+            for (d, &s) in dst.iter_mut().zip(src) {
+                *d = s + *d * inverse;
+            }
+        }
+    }
+    merged
+}
+
+/// Just enough of the zip format for OpenRaster: stored entries, no compression.
+/// The layers are PNGs, which are compressed already.
+struct Zip<W> {
+    out: W,
+    offset: u32,
+    /// Time and date in MS-DOS format.
+    modified: (u16, u16),
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+impl<W: Write> Zip<W> {
+    fn new(out: W, time: SystemTime) -> Self {
+        let [year, month, day, hour, min, sec] = template::utc(time);
+        let date = ((year.max(1980) - 1980) << 9 | month << 5 | day) as u16;
+        let time = (hour << 11 | min << 5 | sec / 2) as u16;
+        Zip {
+            out,
+            offset: 0,
+            modified: (time, date),
+            entries: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let entry = Entry {
+            name: name.to_string(),
+            crc: crc32(&[data]),
+            size: limit32(data.len() as u64)?,
+            offset: self.offset,
+        };
+        let mut header = Vec::with_capacity(30 + name.len());
+        put32(&mut header, 0x0403_4b50);
+        self.put_common(&mut header, &entry);
+        // No extra field
+        put16(&mut header, 0);
+        header.extend_from_slice(name.as_bytes());
+        self.out.write_all(&header)?;
+        self.out.write_all(data)?;
+        self.offset = limit32(u64::from(self.offset) + (header.len() + data.len()) as u64)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Writes the central directory and returns the size of the archive.
+    fn finish(mut self) -> io::Result<u64> {
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            put32(&mut directory, 0x0201_4b50);
+            // Made by version 2.0
+            put16(&mut directory, 20);
+            self.put_common(&mut directory, entry);
+            // Extra field, comment, disk, internal and external attributes
+            for _ in 0..4 {
+                put16(&mut directory, 0);
+            }
+            put32(&mut directory, 0);
+            put32(&mut directory, entry.offset);
+            directory.extend_from_slice(entry.name.as_bytes());
+        }
+        let count = limit16(self.entries.len())?;
+        let size = limit32(directory.len() as u64)?;
+        // The directory must start within reach of the 32 bit offset too
+        limit32(u64::from(self.offset) + directory.len() as u64)?;
+        put32(&mut directory, 0x0605_4b50);
+        // This disk and the disk the directory starts on
+        put16(&mut directory, 0);
+        put16(&mut directory, 0);
+        put16(&mut directory, count);
+        put16(&mut directory, count);
+        put32(&mut directory, size);
+        put32(&mut directory, self.offset);
+        // No comment
+        put16(&mut directory, 0);
+        self.out.write_all(&directory)?;
+        self.out.flush()?;
+        Ok(u64::from(self.offset) + directory.len() as u64)
+    }
+
+    /// Fields shared by local headers and the central directory,
+    /// from the version needed up to the file name length.
+    fn put_common(&self, out: &mut Vec<u8>, entry: &Entry) {
+        // Version 2.0, no flags, stored
+        put16(out, 20);
+        put16(out, 0);
+        put16(out, 0);
+        put16(out, self.modified.0);
+        put16(out, self.modified.1);
+        put32(out, entry.crc);
+        put32(out, entry.size);
+        put32(out, entry.size);
+        put16(out, entry.name.len() as u16);
+    }
+}
+
+/// Sizes and offsets past `u32::MAX` need ZIP64 records, which are not
+/// written, and the largest value itself marks one, so it is refused too.
+fn limit32(n: u64) -> io::Result<u32> {
+    if n < u64::from(u32::max_value()) {
+        Ok(n as u32)
+    } else {
+        Err(too_large())
+    }
+}
+
+/// Entry counts have the same limit, in 16 bits.
+fn limit16(n: usize) -> io::Result<u16> {
+    if n < usize::from(u16::max_value()) {
+        Ok(n as u16)
+    } else {
+        Err(too_large())
+    }
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "layers too large for an OpenRaster file without ZIP64",
+    )
+}
+
+fn put16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn le16(data: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([data[at], data[at + 1]])
+    }
+
+    fn le32(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+    }
+
+    /// Checks the central directory against the local headers
+    /// and returns every entry's name and data in order.
+    fn unzip(zip: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let end = zip.len() - 22;
+        assert_eq!(le32(zip, end), 0x0605_4b50);
+        let count = le16(zip, end + 10) as usize;
+        assert_eq!(le16(zip, end + 8) as usize, count);
+        let size = le32(zip, end + 12) as usize;
+        let mut central = le32(zip, end + 16) as usize;
+        assert_eq!(central + size, end);
+        let mut entries = vec![];
+        for _ in 0..count {
+            assert_eq!(le32(zip, central), 0x0201_4b50);
+            // Version needed up to the name length matches the local header
+            let common = &zip[central + 6..central + 30];
+            let name_len = le16(zip, central + 28) as usize;
+            let local = le32(zip, central + 42) as usize;
+            let name = &zip[central + 46..central + 46 + name_len];
+            assert_eq!(le32(zip, local), 0x0403_4b50);
+            assert_eq!(&zip[local + 4..local + 28], common);
+            // Stored, without extra field
+            assert_eq!(le16(zip, local + 8), 0);
+            assert_eq!(le16(zip, local + 28), 0);
+            assert_eq!(&zip[local + 30..local + 30 + name_len], name);
+            let size = le32(zip, local + 18) as usize;
+            assert_eq!(le32(zip, local + 22) as usize, size);
+            let start = local + 30 + name_len;
+            let data = &zip[start..start + size];
+            assert_eq!(le32(zip, local + 14), crc32(&[data]));
+            entries.push((String::from_utf8(name.to_vec()).unwrap(), data.to_vec()));
+            central += 46 + name_len;
+        }
+        entries
+    }
+
+    fn decode_png(png: &[u8]) -> ((u32, u32), Vec<u8>) {
+        let (info, mut reader) = png::Decoder::new(png).read_info().unwrap();
+        let mut pixels = vec![0; info.buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        ((info.width, info.height), pixels)
+    }
+
+    #[test]
+    fn writes_a_readable_archive() {
+        let layers = [
+            Layer {
+                name: "background".to_string(),
+                data: vec![1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0],
+            },
+            Layer {
+                name: "dots & <lines>".to_string(),
+                data: vec![0.0, 0.5, 0.0, 0.5, 0.0, 0.0, 0.0, 0.0],
+            },
+        ];
+        let path = std::env::temp_dir().join(format!("screenshot-{}-layers.ora", process::id()));
+        // 2020-01-02 03:04:06 UTC
+        let time = UNIX_EPOCH + Duration::from_secs(1_577_934_246);
+        let bytes = write(&path, (2, 1), &layers, Transfer::Linear, time).unwrap();
+        let zip = fs::read(&path).unwrap();
+        fs::remove_file(&path).ok();
+        assert_eq!(bytes, zip.len() as u64);
+
+        // Readers look for the uncompressed mimetype at a fixed offset
+        assert_eq!(&zip[30..38], b"mimetype");
+        assert_eq!(&zip[38..54], b"image/openraster");
+        assert_eq!(le16(&zip, 10), 3 << 11 | 4 << 5 | 3);
+        assert_eq!(le16(&zip, 12), 40 << 9 | 1 << 5 | 2);
+
+        let entries = unzip(&zip);
+        let names: Vec<_> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "mimetype",
+                "stack.xml",
+                "data/layer0.png",
+                "data/layer1.png",
+                "mergedimage.png",
+                "Thumbnails/thumbnail.png"
+            ]
+        );
+        let stack = String::from_utf8(entries[1].1.clone()).unwrap();
+        assert!(stack.contains("<image version=\"0.0.5\" w=\"2\" h=\"1\">"));
+        let top = stack.find("name=\"dots &amp; &lt;lines&gt;\" src=\"data/layer1.png\"");
+        let bottom = stack.find("name=\"background\" src=\"data/layer0.png\"");
+        assert!(top.unwrap() < bottom.unwrap());
+
+        // Layers are stored with straight alpha
+        let (dims, top) = decode_png(&entries[3].1);
+        assert_eq!(dims, (2, 1));
+        assert_eq!(top, [0, 255, 0, 128, 0, 0, 0, 0]);
+        let (_, merged) = decode_png(&entries[4].1);
+        assert_eq!(merged, [128, 128, 0, 255, 0, 0, 255, 255]);
+        let (thumbnail_dims, _) = decode_png(&entries[5].1);
+        assert_eq!(thumbnail_dims, (2, 1));
+    }
+
+    #[test]
+    fn sizes_past_32_bits_are_refused() {
+        assert_eq!(
+            limit32(u64::from(u32::max_value()) - 1).unwrap(),
+            u32::max_value() - 1
+        );
+        assert!(limit32(u64::from(u32::max_value())).is_err());
+        assert!(limit16(usize::from(u16::max_value())).is_err());
+        let mut zip = Zip::new(io::sink(), UNIX_EPOCH);
+        zip.add("small", &[0; 10]).unwrap();
+        zip.offset = u32::max_value() - 64;
+        assert!(zip.add("large", &[0; 64]).is_err());
+        // The directory cannot start past the limit either
+        zip.offset = u32::max_value() - 40;
+        assert!(zip.finish().is_err());
+    }
+}
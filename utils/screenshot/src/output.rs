@@ -0,0 +1,81 @@
+use super::template::{self, FilenameTemplate};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// How `Shots` avoids replacing captures from earlier runs.
+///
+/// Unless overwriting, a name rendered from a template without `{frame}`
+/// that is already taken gets a suffix, as in `poster-2.png`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputPolicy {
+    /// Number from 1 in every directory and replace existing files.
+    Overwrite,
+    /// Continue numbering after the highest numbered file already in the directory.
+    Resume,
+    /// Write each run into its own `dist/{timestamp}` directory.
+    SessionDir,
+}
+
+impl Default for OutputPolicy {
+    fn default() -> Self {
+        OutputPolicy::Resume
+    }
+}
+
+/// Creates a fresh `{root}/{timestamp}` directory for this run,
+/// adding a suffix if another run started in the same second.
+pub(crate) fn create_session_dir(root: &Path) -> io::Result<PathBuf> {
+    let stamp = template::timestamp(SystemTime::now());
+    let mut dir = root.join(&stamp);
+    let mut n = 1;
+    while dir.exists() {
+        n += 1;
+        dir = root.join(format!("{}-{}", stamp, n));
+    }
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// `path`, or the first of `{stem}-2.{ext}`, `{stem}-3.{ext}`
+/// and so on that doesn't exist yet.
+pub(crate) fn free_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path.extension().map(|e| e.to_string_lossy().into_owned());
+    let mut free = path.to_path_buf();
+    let mut n = 1;
+    while free.exists() {
+        n += 1;
+        let name = match &extension {
+            Some(extension) => format!("{}-{}.{}", stem, n, extension),
+            None => format!("{}-{}", stem, n),
+        };
+        free = path.with_file_name(name);
+    }
+    free
+}
+
+/// Highest frame number among the files in `dir` matching `template`, or 0.
+pub(crate) fn highest_frame(
+    dir: &Path,
+    template: &FilenameTemplate,
+    vars: &HashMap<String, String>,
+) -> usize {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            template.frame_number(name.to_str()?, vars)
+        })
+        .max()
+        .unwrap_or(0)
+}
@@ -0,0 +1,317 @@
+use super::region::FrameThumbnail;
+use super::replay;
+use super::sink::{FrameInfo, FrameSink};
+use std::collections::BTreeMap;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Work handed to a worker.
+enum Job<T> {
+    /// A frame ready to be encoded.
+    Frame {
+        info: FrameInfo,
+        data: Vec<u8>,
+        thumbnail: Option<FrameThumbnail>,
+        tag: T,
+    },
+    /// A frame to compress for the instant replay.
+    Replay(replay::Frame),
+}
+
+/// A job the pool has finished, handed back in submission order.
+pub(crate) enum Done<T> {
+    Written(Written<T>),
+    Compressed(replay::Compressed),
+}
+
+/// A frame the sink has written or failed to write.
+pub(crate) struct Written<T> {
+    pub(crate) info: FrameInfo,
+    /// Bytes written, or the error from the sink.
+    pub(crate) result: io::Result<u64>,
+    /// Time spent in the sink.
+    pub(crate) elapsed: Duration,
+    /// Outcome of writing the thumbnail, if the frame has one.
+    pub(crate) thumbnail: io::Result<()>,
+    /// Whatever the saving thread needs to finish the frame.
+    pub(crate) tag: T,
+}
+
+/// Threads that encode frames through their own copy of the sink,
+/// and compress frames for the instant replay.
+///
+/// Jobs may finish in any order but are handed back in
+/// the order they were submitted.
+pub(crate) struct Pool<T> {
+    jobs: Option<Sender<(usize, Job<T>)>>,
+    results: Receiver<(usize, Done<T>)>,
+    workers: Vec<JoinHandle<()>>,
+    finished: BTreeMap<usize, Done<T>>,
+    next_seq: usize,
+    next_done: usize,
+}
+
+impl<T: Send + 'static> Pool<T> {
+    /// Starts `threads` workers if `sink` can be copied, otherwise returns `None`.
+    pub(crate) fn new(sink: &dyn FrameSink, threads: usize) -> Option<Self> {
+        let (jobs, job_in) = mpsc::channel::<(usize, Job<T>)>();
+        let (result_out, results) = mpsc::channel();
+        let job_in = Arc::new(Mutex::new(job_in));
+        let mut workers = Vec::with_capacity(threads);
+        for _ in 0..threads {
+            let mut sink = sink.try_clone()?;
+            let job_in = job_in.clone();
+            let result_out = result_out.clone();
+            workers.push(thread::spawn(move || loop {
+                let (seq, job) = match job_in.lock().map(|jobs| jobs.recv()) {
+                    Ok(Ok(job)) => job,
+                    _ => return,
+                };
+                let done = match job {
+                    Job::Frame {
+                        info,
+                        data,
+                        thumbnail,
+                        tag,
+                    } => Done::Written(write(&mut *sink, info, &data, thumbnail, tag)),
+                    Job::Replay(frame) => Done::Compressed(frame.compress()),
+                };
+                if result_out.send((seq, done)).is_err() {
+                    return;
+                }
+            }));
+        }
+        Some(Pool {
+            jobs: Some(jobs),
+            results,
+            workers,
+            finished: BTreeMap::new(),
+            next_seq: 0,
+            next_done: 0,
+        })
+    }
+
+    /// Jobs submitted but not yet handed back.
+    pub(crate) fn in_flight(&self) -> usize {
+        self.next_seq - self.next_done
+    }
+
+    /// Number of jobs that keeps every worker busy without piling up memory.
+    pub(crate) fn capacity(&self) -> usize {
+        self.workers.len() * 2
+    }
+
+    /// Queues a frame, followed by its thumbnail once the sink has written it.
+    pub(crate) fn submit(
+        &mut self,
+        info: FrameInfo,
+        data: Vec<u8>,
+        thumbnail: Option<FrameThumbnail>,
+        tag: T,
+    ) {
+        self.send(Job::Frame {
+            info,
+            data,
+            thumbnail,
+            tag,
+        });
+    }
+
+    /// Queues a frame to be compressed for the instant replay.
+    pub(crate) fn compress(&mut self, frame: replay::Frame) {
+        self.send(Job::Replay(frame));
+    }
+
+    fn send(&mut self, job: Job<T>) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let job = match &self.jobs {
+            Some(jobs) => match jobs.send((seq, job)) {
+                Ok(()) => return,
+                Err(mpsc::SendError((_, job))) => job,
+            },
+            None => job,
+        };
+        let done = match job {
+            Job::Frame { info, tag, .. } => {
+                let error = io::Error::new(io::ErrorKind::Other, "encoding thread stopped");
+                Done::Written(Written {
+                    info,
+                    result: Err(error),
+                    elapsed: Duration::default(),
+                    thumbnail: Ok(()),
+                    tag,
+                })
+            }
+            Job::Replay(frame) => Done::Compressed(frame.compress()),
+        };
+        self.finished.insert(seq, done);
+    }
+
+    /// Hands back finished jobs in order, waiting for at least one if `block` is set.
+    pub(crate) fn finished(&mut self, block: bool) -> Vec<Done<T>> {
+        let mut done = self.take_ready();
+        if block && done.is_empty() && self.in_flight() > 0 {
+            while done.is_empty() {
+                match self.results.recv() {
+                    Ok((seq, finished)) => {
+                        self.finished.insert(seq, finished);
+                    }
+                    // Every worker is gone, so nothing else will finish
+                    Err(_) => break,
+                }
+                done = self.take_ready();
+            }
+        }
+        done
+    }
+
+    /// Waits for every submitted job.
+    pub(crate) fn finish_all(&mut self) -> Vec<Done<T>> {
+        let mut done = Vec::new();
+        while self.in_flight() > 0 {
+            let more = self.finished(true);
+            if more.is_empty() {
+                break;
+            }
+            done.extend(more);
+        }
+        done
+    }
+
+    fn take_ready(&mut self) -> Vec<Done<T>> {
+        for (seq, finished) in self.results.try_iter() {
+            self.finished.insert(seq, finished);
+        }
+        let mut done = Vec::new();
+        while let Some(finished) = self.finished.remove(&self.next_done) {
+            self.next_done += 1;
+            done.push(finished);
+        }
+        done
+    }
+}
+
+/// Writes a frame through `sink`, followed by its thumbnail if that succeeded.
+fn write<T>(
+    sink: &mut dyn FrameSink,
+    info: FrameInfo,
+    data: &[u8],
+    thumbnail: Option<FrameThumbnail>,
+    tag: T,
+) -> Written<T> {
+    let start = Instant::now();
+    // A panicking sink fails its frame instead of taking the worker with it,
+    // which would leave the saving thread waiting for a result forever
+    let write = AssertUnwindSafe(|| sink.write(&info, data));
+    let result = panic::catch_unwind(write)
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "the sink panicked")));
+    let elapsed = start.elapsed();
+    let thumbnail = match (&result, thumbnail) {
+        (Ok(_), Some(thumbnail)) => thumbnail.write(&info, data),
+        _ => Ok(()),
+    };
+    Written {
+        info,
+        result,
+        elapsed,
+        thumbnail,
+        tag,
+    }
+}
+
+impl<T> Drop for Pool<T> {
+    fn drop(&mut self) {
+        // Closing the job channel stops the workers once they are idle
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::SampleFormat;
+    use crate::metadata::Metadata;
+    use std::path::PathBuf;
+    use std::time::SystemTime;
+
+    /// Panics on the frame numbered `panic_at`.
+    #[derive(Clone)]
+    struct PanickingSink {
+        panic_at: usize,
+    }
+
+    impl FrameSink for PanickingSink {
+        fn write(&mut self, info: &FrameInfo, _: &[u8]) -> io::Result<u64> {
+            if info.number == self.panic_at {
+                panic!("frame {}", info.number);
+            }
+            Ok(0)
+        }
+
+        fn try_clone(&self) -> Option<Box<dyn FrameSink>> {
+            Some(Box::new(self.clone()))
+        }
+    }
+
+    fn info(number: usize) -> FrameInfo {
+        FrameInfo {
+            number,
+            dims: (1, 1),
+            sample: SampleFormat::U8,
+            output_dir: PathBuf::new(),
+            path: PathBuf::from(format!("{}.png", number)),
+            metadata: Metadata::new(),
+        }
+    }
+
+    #[test]
+    fn panicking_sink_fails_its_frame() {
+        let mut pool = Pool::new(&PanickingSink { panic_at: 2 }, 2).unwrap();
+        for number in 1..=4 {
+            pool.submit(info(number), vec![0; 4], None, number);
+        }
+        let done = written(pool.finish_all());
+        let tags: Vec<_> = done.iter().map(|done| done.tag).collect();
+        assert_eq!(tags, [1, 2, 3, 4]);
+        let failed: Vec<_> = done.iter().map(|done| done.result.is_err()).collect();
+        assert_eq!(failed, [false, true, false, false]);
+        assert_eq!(pool.in_flight(), 0);
+    }
+
+    #[test]
+    fn replay_frames_keep_their_place() {
+        let mut pool = Pool::new(&PanickingSink { panic_at: 0 }, 3).unwrap();
+        pool.submit(info(1), vec![0; 4], None, 1);
+        let image =
+            replay::Frame::new(SystemTime::UNIX_EPOCH, (1, 1), SampleFormat::U8, vec![7; 4]);
+        pool.compress(image);
+        pool.submit(info(2), vec![0; 4], None, 2);
+        assert_eq!(pool.in_flight(), 3);
+        let done = pool.finish_all();
+        let kinds: Vec<_> = done
+            .iter()
+            .map(|done| match done {
+                Done::Written(written) => Some(written.tag),
+                Done::Compressed(_) => None,
+            })
+            .collect();
+        assert_eq!(kinds, [Some(1), None, Some(2)]);
+    }
+
+    fn written<T>(done: Vec<Done<T>>) -> Vec<Written<T>> {
+        done.into_iter()
+            .filter_map(|done| match done {
+                Done::Written(written) => Some(written),
+                Done::Compressed(_) => None,
+            })
+            .collect()
+    }
+}
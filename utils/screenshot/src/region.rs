@@ -0,0 +1,291 @@
+use super::color::Transfer;
+use super::format::{self, OutputFormat, SampleFormat};
+use super::sink::FrameInfo;
+use super::NUM_COLOURS;
+use nannou::image::{self, imageops, RgbaImage};
+use std::borrow::Cow;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A rectangle of the frame in pixels, from its top left corner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Resampling filter used to shrink thumbnails, from fastest to sharpest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+/// A downscaled copy written next to every saved image, see `Shots::thumbnails`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Thumbnail {
+    /// Largest width of the thumbnail. The aspect ratio is kept.
+    pub width: usize,
+    /// Largest height of the thumbnail.
+    pub height: usize,
+    pub filter: Filter,
+}
+
+impl Region {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Region {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The part of the region inside a frame of `dims`, if any.
+    pub(crate) fn clip(self, dims: (usize, usize)) -> Option<Region> {
+        let right = (self.x + self.width).min(dims.0);
+        let bottom = (self.y + self.height).min(dims.1);
+        if self.x >= right || self.y >= bottom {
+            return None;
+        }
+        Some(Region::new(self.x, self.y, right - self.x, bottom - self.y))
+    }
+
+    /// Copies the region out of RGBA `data` of `dims`.
+    /// The region must already be clipped to the frame.
+    pub(crate) fn crop<'a>(
+        self,
+        data: &'a [u8],
+        dims: (usize, usize),
+        sample: SampleFormat,
+    ) -> Cow<'a, [u8]> {
+        if (self.x, self.y) == (0, 0) && (self.width, self.height) == dims {
+            return Cow::Borrowed(data);
+        }
+        let pixel = NUM_COLOURS * sample.bytes_per_sample();
+        let stride = dims.0 * pixel;
+        let mut out = Vec::with_capacity(self.width * self.height * pixel);
+        for row in data.chunks_exact(stride).skip(self.y).take(self.height) {
+            out.extend_from_slice(&row[self.x * pixel..(self.x + self.width) * pixel]);
+        }
+        Cow::Owned(out)
+    }
+}
+
+/// The thumbnail of one frame, written once the frame itself is encoded.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FrameThumbnail {
+    pub(crate) thumbnail: Thumbnail,
+    /// Float samples are linear, so they get the sRGB OETF before
+    /// being shrunk to 8 bit.
+    pub(crate) linear: bool,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Triangle
+    }
+}
+
+impl Filter {
+    fn filter_type(self) -> image::FilterType {
+        match self {
+            Filter::Nearest => image::FilterType::Nearest,
+            Filter::Triangle => image::FilterType::Triangle,
+            Filter::CatmullRom => image::FilterType::CatmullRom,
+            Filter::Gaussian => image::FilterType::Gaussian,
+            Filter::Lanczos3 => image::FilterType::Lanczos3,
+        }
+    }
+}
+
+impl Thumbnail {
+    /// A thumbnail that fits in `width` x `height`, shrunk with `Filter::Triangle`.
+    pub fn new(width: usize, height: usize) -> Self {
+        Thumbnail {
+            width,
+            height,
+            filter: Filter::default(),
+        }
+    }
+
+    pub fn filter(self, filter: Filter) -> Self {
+        Thumbnail { filter, ..self }
+    }
+
+    /// Where the thumbnail of the image at `path` goes:
+    /// `frame_0001.png` gets `frame_0001_thumb.png`.
+    pub(crate) fn path(path: &Path) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}_thumb.png", stem))
+    }
+
+    /// Size of the thumbnail of a frame of `dims`.
+    /// Frames that already fit are not enlarged.
+    pub(crate) fn dims(&self, dims: (usize, usize)) -> (usize, usize) {
+        let scale = (self.width as f64 / dims.0 as f64)
+            .min(self.height as f64 / dims.1 as f64)
+            .min(1.0);
+        let w = (dims.0 as f64 * scale).round().max(1.0);
+        let h = (dims.1 as f64 * scale).round().max(1.0);
+        (w as usize, h as usize)
+    }
+
+    /// Shrinks RGBA `data` of `dims` and writes it as an 8 bit PNG to `path`.
+    pub(crate) fn write(
+        &self,
+        path: &Path,
+        data: &[u8],
+        dims: (usize, usize),
+        sample: SampleFormat,
+    ) -> io::Result<()> {
+        let (small, dims) = self.shrink(data, dims, sample)?;
+        format::write_image(path, &small, dims, SampleFormat::U8, OutputFormat::Png)
+    }
+
+    /// Shrinks RGBA `data` of `dims` to 8 bit RGBA of the returned size.
+    pub(crate) fn shrink(
+        &self,
+        data: &[u8],
+        dims: (usize, usize),
+        sample: SampleFormat,
+    ) -> io::Result<(Vec<u8>, (usize, usize))> {
+        let rgba = format::to_u8(data, sample).into_owned();
+        let image = RgbaImage::from_raw(dims.0 as u32, dims.1 as u32, rgba)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "frame size mismatch"))?;
+        let (w, h) = self.dims(dims);
+        let small = imageops::resize(&image, w as u32, h as u32, self.filter.filter_type());
+        Ok((small.into_raw(), (w, h)))
+    }
+}
+
+impl FrameThumbnail {
+    /// Writes the thumbnail of the frame at `info.path` next to it.
+    pub(crate) fn write(&self, info: &FrameInfo, data: &[u8]) -> io::Result<()> {
+        let path = Thumbnail::path(&info.path);
+        if self.linear && info.sample == SampleFormat::F32 {
+            let data = Transfer::Srgb.encode_f32_bytes(data);
+            self.thumbnail.write(&path, &data, info.dims, info.sample)
+        } else {
+            self.thumbnail.write(&path, data, info.dims, info.sample)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::Metadata;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn clip_to_the_frame() {
+        let dims = (10, 8);
+        let inside = Region::new(2, 3, 4, 2);
+        assert_eq!(inside.clip(dims), Some(inside));
+        assert_eq!(
+            Region::new(6, 5, 10, 10).clip(dims),
+            Some(Region::new(6, 5, 4, 3))
+        );
+        assert_eq!(
+            Region::new(0, 0, 20, 20).clip(dims),
+            Some(Region::new(0, 0, 10, 8))
+        );
+        // Outside, touching the edge, or empty
+        assert_eq!(Region::new(10, 0, 5, 5).clip(dims), None);
+        assert_eq!(Region::new(0, 8, 5, 5).clip(dims), None);
+        assert_eq!(Region::new(20, 20, 5, 5).clip(dims), None);
+        assert_eq!(Region::new(2, 2, 0, 5).clip(dims), None);
+        assert_eq!(Region::new(2, 2, 5, 0).clip(dims), None);
+    }
+
+    #[test]
+    fn crop_copies_rows() {
+        // Every pixel holds its index in all four samples
+        let dims = (4, 3);
+        let data: Vec<u8> = (0..12).flat_map(|i| vec![i; 4]).collect();
+        let region = Region::new(1, 1, 2, 2);
+        let cropped = region.crop(&data, dims, SampleFormat::U8);
+        let pixels: Vec<u8> = cropped.chunks_exact(4).map(|p| p[0]).collect();
+        assert_eq!(pixels, [5, 6, 9, 10]);
+        // A partly outside region is clipped first
+        let edge = Region::new(3, 2, 5, 5).clip(dims).unwrap();
+        assert_eq!(&*edge.crop(&data, dims, SampleFormat::U8), &[11; 4]);
+        let whole = Region::new(0, 0, 4, 3);
+        match whole.crop(&data, dims, SampleFormat::U8) {
+            Cow::Borrowed(whole) => assert_eq!(whole, &data[..]),
+            Cow::Owned(_) => panic!("whole frame was copied"),
+        }
+    }
+
+    #[test]
+    fn crop_wide_samples() {
+        let dims = (2, 1);
+        let data: Vec<u8> = (0..16).collect();
+        let right = Region::new(1, 0, 1, 1).crop(&data, dims, SampleFormat::U16);
+        assert_eq!(&*right, &data[8..]);
+    }
+
+    #[test]
+    fn thumbnails_keep_the_aspect_ratio() {
+        let thumbnail = Thumbnail::new(256, 256);
+        assert_eq!(thumbnail.dims((1920, 1080)), (256, 144));
+        assert_eq!(thumbnail.dims((1080, 1920)), (144, 256));
+        assert_eq!(thumbnail.dims((512, 512)), (256, 256));
+        // Small frames are not enlarged, thin ones keep a pixel
+        assert_eq!(thumbnail.dims((100, 50)), (100, 50));
+        assert_eq!(thumbnail.dims((10000, 1)), (256, 1));
+        assert_eq!(Thumbnail::new(300, 100).dims((800, 600)), (133, 100));
+    }
+
+    #[test]
+    fn thumbnail_path() {
+        assert_eq!(
+            Thumbnail::path(Path::new("dist/frame_0001.png")),
+            Path::new("dist/frame_0001_thumb.png")
+        );
+        assert_eq!(
+            Thumbnail::path(Path::new("dist/frame.exr")),
+            Path::new("dist/frame_thumb.png")
+        );
+    }
+
+    #[test]
+    fn linear_floats_are_srgb_encoded() {
+        let path = std::env::temp_dir().join(format!("screenshot-{}-thumb.exr", process::id()));
+        let info = FrameInfo {
+            number: 1,
+            dims: (1, 1),
+            sample: SampleFormat::F32,
+            output_dir: std::env::temp_dir(),
+            path: path.clone(),
+            metadata: Metadata::new(),
+        };
+        let data: Vec<u8> = [0.5f32, 0.5, 0.5, 1.0]
+            .iter()
+            .flat_map(|s| s.to_ne_bytes().to_vec())
+            .collect();
+        let mut pixels = vec![];
+        for &linear in &[true, false] {
+            let thumbnail = FrameThumbnail {
+                thumbnail: Thumbnail::new(16, 16),
+                linear,
+            };
+            thumbnail.write(&info, &data).unwrap();
+            let thumb = Thumbnail::path(&path);
+            let png = fs::read(&thumb).unwrap();
+            fs::remove_file(&thumb).ok();
+            let (info, mut reader) = png::Decoder::new(&png[..]).read_info().unwrap();
+            let mut pixel = vec![0; info.buffer_size()];
+            reader.next_frame(&mut pixel).unwrap();
+            pixels.push(pixel);
+        }
+        assert_eq!(pixels, [[188, 188, 188, 255], [128, 128, 128, 255]]);
+    }
+}
@@ -0,0 +1,243 @@
+use super::format::SampleFormat;
+use super::ImageData;
+use deflate::Compression;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Upper bound on the compressed frames kept for an instant replay.
+const MAX_BYTES: usize = 512 * 1024 * 1024;
+
+/// Compressed copies of the most recent frames, kept on the saving thread.
+pub(crate) struct Replay {
+    length: Duration,
+    frames: VecDeque<Compressed>,
+    bytes: usize,
+}
+
+/// A frame read back for the ring, to be compressed on an encoding thread.
+pub(crate) struct Frame {
+    arrived: Instant,
+    time: SystemTime,
+    dims: (usize, usize),
+    sample: SampleFormat,
+    data: Vec<u8>,
+}
+
+/// A frame as kept in the ring.
+pub(crate) struct Compressed {
+    arrived: Instant,
+    time: SystemTime,
+    dims: (usize, usize),
+    sample: SampleFormat,
+    data: Vec<u8>,
+}
+
+/// A frame taken back out of the ring.
+pub(crate) struct Replayed {
+    pub(crate) time: SystemTime,
+    dims: (usize, usize),
+    sample: SampleFormat,
+    data: Vec<u8>,
+}
+
+impl Replay {
+    pub(crate) fn new(length: Duration) -> Self {
+        Replay {
+            length,
+            frames: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+
+    pub(crate) fn set_length(&mut self, length: Duration) {
+        self.length = length;
+        self.trim();
+    }
+
+    /// Compresses a frame into the ring on this thread, see `Replay::insert`.
+    /// Returns false if the GPU still held the buffer at `deadline`.
+    pub(crate) fn push<B: ImageData>(
+        &mut self,
+        time: SystemTime,
+        image: &B,
+        deadline: Instant,
+    ) -> bool {
+        let arrived = Instant::now();
+        let data = match image.read_by(deadline, compress) {
+            Some(data) => data,
+            None => return false,
+        };
+        self.insert(Compressed {
+            arrived,
+            time,
+            dims: image.dims(),
+            sample: image.sample_format(),
+            data,
+        });
+        true
+    }
+
+    /// Adds a frame to the ring, dropping frames older than the replay length.
+    pub(crate) fn insert(&mut self, frame: Compressed) {
+        self.bytes += frame.data.len();
+        self.frames.push_back(frame);
+        self.trim();
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Decompresses and removes every frame, oldest first.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = Result<Replayed, String>> + '_ {
+        self.bytes = 0;
+        self.frames.drain(..).map(|frame| {
+            Ok(Replayed {
+                time: frame.time,
+                dims: frame.dims,
+                sample: frame.sample,
+                data: inflate::inflate_bytes_zlib(&frame.data)?,
+            })
+        })
+    }
+
+    fn trim(&mut self) {
+        let newest = match self.frames.back() {
+            Some(frame) => frame.arrived,
+            None => return,
+        };
+        while let Some(oldest) = self.frames.front() {
+            let expired = newest.duration_since(oldest.arrived) > self.length;
+            if !expired && self.bytes <= MAX_BYTES {
+                break;
+            }
+            self.bytes -= oldest.data.len();
+            self.frames.pop_front();
+        }
+    }
+}
+
+impl Frame {
+    pub(crate) fn new(
+        time: SystemTime,
+        dims: (usize, usize),
+        sample: SampleFormat,
+        data: Vec<u8>,
+    ) -> Self {
+        Frame {
+            arrived: Instant::now(),
+            time,
+            dims,
+            sample,
+            data,
+        }
+    }
+
+    /// Copies `image` out so its buffer can be reused while it is compressed.
+    /// Returns `None` if the GPU still held the buffer at `deadline`.
+    pub(crate) fn read<B: ImageData>(
+        time: SystemTime,
+        image: &B,
+        deadline: Instant,
+    ) -> Option<Self> {
+        let data = image.read_by(deadline, |data| data.to_vec())?;
+        Some(Frame::new(time, image.dims(), image.sample_format(), data))
+    }
+
+    pub(crate) fn compress(self) -> Compressed {
+        Compressed {
+            arrived: self.arrived,
+            time: self.time,
+            dims: self.dims,
+            sample: self.sample,
+            data: compress(&self.data),
+        }
+    }
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    deflate::deflate_bytes_zlib_conf(data, Compression::Fast)
+}
+
+impl ImageData for Replayed {
+    fn dims(&self) -> (usize, usize) {
+        self.dims
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        self.sample
+    }
+
+    fn read<R, F: FnOnce(&[u8]) -> R>(&self, f: F) -> Option<R> {
+        Some(f(&self.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A compressed frame that arrived `ms` after `start`.
+    fn frame(start: Instant, ms: u64, value: u8) -> Compressed {
+        Compressed {
+            arrived: start + Duration::from_millis(ms),
+            time: SystemTime::UNIX_EPOCH + Duration::from_millis(ms),
+            dims: (1, 1),
+            sample: SampleFormat::U8,
+            data: compress(&[value; 4]),
+        }
+    }
+
+    fn values(replay: &mut Replay) -> Vec<u8> {
+        replay.drain().map(|frame| frame.unwrap().data[0]).collect()
+    }
+
+    #[test]
+    fn ring_trims_to_its_length() {
+        let start = Instant::now();
+        let mut replay = Replay::new(Duration::from_millis(100));
+        for (i, ms) in [0, 40, 90, 130, 190].iter().enumerate() {
+            replay.insert(frame(start, *ms, i as u8));
+        }
+        // Kept if no older than the length, counted from the newest frame
+        assert_eq!(replay.len(), 3);
+        assert_eq!(values(&mut replay), [2, 3, 4]);
+        assert_eq!(replay.len(), 0);
+        assert_eq!(replay.bytes, 0);
+    }
+
+    #[test]
+    fn shortening_trims_at_once() {
+        let start = Instant::now();
+        let mut replay = Replay::new(Duration::from_secs(10));
+        for (i, ms) in [0, 500, 1000].iter().enumerate() {
+            replay.insert(frame(start, *ms, i as u8));
+        }
+        assert_eq!(replay.len(), 3);
+        replay.set_length(Duration::from_millis(500));
+        assert_eq!(values(&mut replay), [1, 2]);
+    }
+
+    #[test]
+    fn frames_come_back_as_captured() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(5);
+        let data: Vec<u8> = (0..32).collect();
+        let image = Replayed {
+            time,
+            dims: (2, 2),
+            sample: SampleFormat::U16,
+            data: data.clone(),
+        };
+        let mut replay = Replay::new(Duration::from_secs(1));
+        assert!(replay.push(time, &image, Instant::now()));
+        let frame = Frame::read(time, &image, Instant::now()).unwrap();
+        replay.insert(frame.compress());
+        for frame in replay.drain() {
+            let frame = frame.unwrap();
+            assert_eq!(frame.time, time);
+            assert_eq!(frame.dims(), (2, 2));
+            assert_eq!(frame.sample_format(), SampleFormat::U16);
+            assert_eq!(frame.data, data);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use super::format::{self, OutputFormat, SampleFormat};
+use super::metadata::Metadata;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::PathBuf;
+
+/// Describes a captured frame handed to a `FrameSink`.
+#[derive(Clone, Debug)]
+pub struct FrameInfo {
+    /// Number of the frame in the current output directory, starting at 1.
+    pub number: usize,
+    /// Width and height of the frame in pixels.
+    pub dims: (usize, usize),
+    /// Precision of the samples in `data`.
+    pub sample: SampleFormat,
+    /// Directory the frame belongs in.
+    pub output_dir: PathBuf,
+    /// Path rendered from the filename template, inside `output_dir`.
+    pub path: PathBuf,
+    /// Text to store with the frame, see `Metadata`.
+    pub metadata: Metadata,
+}
+
+/// Destination for frames coming out of the saving thread.
+///
+/// `data` is tightly packed RGBA in the precision given by `info.sample`,
+/// `dims.0 * dims.1 * NUM_COLOURS * info.sample.bytes_per_sample()` bytes long.
+///
+/// Errors are passed on to `Shots::errors` rather than stopping the saving thread.
+pub trait FrameSink: Send {
+    /// Returns the number of bytes written for the frame, see `Stats::bytes_written`.
+    fn write(&mut self, info: &FrameInfo, data: &[u8]) -> io::Result<u64>;
+
+    /// Substituted for `{ext}` in the filename template.
+    fn extension(&self) -> &str {
+        "png"
+    }
+
+    /// Called once every pending frame has been written, when a recording or
+    /// timelapse stops, the output directory changes or `Shots` is flushed.
+    /// Returns the number of bytes written while flushing, such as a trailer.
+    fn flush(&mut self) -> io::Result<u64> {
+        Ok(0)
+    }
+
+    /// A copy of the sink for another encoding thread, see `Shots::encoding_threads`.
+    ///
+    /// Only sinks that write every frame independently, such as one file
+    /// per frame, should return one. Streams like GIF keep the default.
+    fn try_clone(&self) -> Option<Box<dyn FrameSink>> {
+        None
+    }
+}
+
+/// Closures report no bytes written.
+impl<F> FrameSink for F
+where
+    F: FnMut(&FrameInfo, &[u8]) -> io::Result<()> + Send,
+{
+    fn write(&mut self, info: &FrameInfo, data: &[u8]) -> io::Result<u64> {
+        self(info, data).map(|()| 0)
+    }
+}
+
+/// Writes every frame as an 8 bit PNG file with its metadata to `info.path`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PngSink;
+
+/// Writes every frame to `info.path` in the chosen `OutputFormat`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImageSink {
+    format: OutputFormat,
+}
+
+impl ImageSink {
+    pub fn new(format: OutputFormat) -> Self {
+        ImageSink { format }
+    }
+}
+
+impl FrameSink for PngSink {
+    fn write(&mut self, info: &FrameInfo, data: &[u8]) -> io::Result<u64> {
+        ImageSink::new(OutputFormat::Png).write(info, data)
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn FrameSink>> {
+        Some(Box::new(*self))
+    }
+}
+
+impl FrameSink for ImageSink {
+    fn write(&mut self, info: &FrameInfo, data: &[u8]) -> io::Result<u64> {
+        format::write_file(
+            &info.path,
+            data,
+            info.dims,
+            info.sample,
+            self.format,
+            &info.metadata,
+        )
+    }
+
+    fn extension(&self) -> &str {
+        self.format.extension()
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn FrameSink>> {
+        Some(Box::new(*self))
+    }
+}
+
+/// Counts the bytes written through it, for sinks to report.
+pub(crate) struct Counter<W> {
+    inner: W,
+    bytes: u64,
+}
+
+impl<W> Counter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Counter { inner, bytes: 0 }
+    }
+
+    /// Bytes written since the last call.
+    pub(crate) fn take(&mut self) -> u64 {
+        mem::replace(&mut self.bytes, 0)
+    }
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for Counter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Counters kept by `Shots` since it was created, see `Shots::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Frames `capture` tried to copy for shots, recordings, timelapses,
+    /// posters and the instant replay. A dropped shot is counted again on retry.
+    pub requested: usize,
+    /// Frames copied into a buffer and handed to the saving thread.
+    pub captured: usize,
+    /// Frames written by the sink.
+    pub written: usize,
+    /// Frames the sink failed to write.
+    pub failed: usize,
+    /// Frames the GPU did not release in time.
+    pub lost: usize,
+    /// Frames skipped by the `CapturePolicy` because no buffer was free.
+    pub dropped: usize,
+    /// Frames captured but not yet written.
+    pub queue_depth: usize,
+    /// Time spent in the sink over all written frames.
+    pub encode_time: Duration,
+    /// Bytes the sink reported writing, see `FrameSink::write`.
+    /// Closure sinks report none.
+    pub bytes_written: u64,
+}
+
+impl Stats {
+    pub fn average_encode_time(&self) -> Duration {
+        match self.written {
+            0 => Duration::default(),
+            n => self.encode_time / n as u32,
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} requested, {} captured, {} written, {} failed, {} lost, {} dropped, \
+             {} queued, {:.1} ms per frame, {:.1} MB",
+            self.requested,
+            self.captured,
+            self.written,
+            self.failed,
+            self.lost,
+            self.dropped,
+            self.queue_depth,
+            self.average_encode_time().as_secs_f64() * 1000.0,
+            self.bytes_written as f64 / 1_000_000.0
+        )
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The template used when none is set, e.g. `screenshot12.png`.
+pub const DEFAULT_TEMPLATE: &str = "screenshot{frame}.{ext}";
+
+/// File name pattern for saved frames.
+///
+/// Variables are written as `{name}` and may carry a zero padding
+/// width as `{name:05}`. Built in variables are
+///
+/// - `frame`: number of the frame in the output directory, starting at 1
+/// - `sketch`: name of the sketch directory
+/// - `timestamp`: UTC time of writing as `YYYYMMDD-HHMMSS`
+/// - `ext`: file extension of the sink
+///
+/// Any other variable is looked up in the values set with `Shots::set_var`
+/// and renders as an empty string while unset. Use `{{` and `}}` for
+/// literal braces.
+///
+/// Values can't leave the output directory: path separators, `:` and
+/// the other characters Windows reserves render as `_`, and so does
+/// every dot of a value made only of dots, like `..`.
+///
+/// ```
+/// # use screenshot::FilenameTemplate;
+/// let template = FilenameTemplate::parse("{sketch}_{seed}_{frame:05}_{timestamp}.{ext}").unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilenameTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Var { name: String, width: usize },
+}
+
+/// Returned by `FilenameTemplate::parse` for malformed templates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemplateError {
+    template: String,
+    reason: &'static str,
+}
+
+impl FilenameTemplate {
+    pub fn parse(template: &str) -> Result<Self, TemplateError> {
+        let err = |reason| TemplateError {
+            template: template.to_string(),
+            reason,
+        };
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut var = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => var.push(c),
+                            None => return Err(err("unclosed `{`")),
+                        }
+                    }
+                    let mut split = var.splitn(2, ':');
+                    let name = split.next().unwrap_or("").trim();
+                    if name.is_empty() {
+                        return Err(err("empty variable name"));
+                    }
+                    let width = match split.next() {
+                        Some(spec) if spec.starts_with('0') && spec.len() > 1 => spec[1..]
+                            .parse()
+                            .map_err(|_| err("padding must be written as `:0N`"))?,
+                        Some(_) => return Err(err("padding must be written as `:0N`")),
+                        None => 0,
+                    };
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(literal.split_off(0)));
+                    }
+                    parts.push(Part::Var {
+                        name: name.to_string(),
+                        width,
+                    });
+                }
+                '}' => return Err(err("unmatched `}`")),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(FilenameTemplate { parts })
+    }
+
+    /// Renders the template, looking variables up in `vars`.
+    pub fn render(&self, vars: &HashMap<String, String>) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => out.push_str(s),
+                Part::Var { name, width } => {
+                    let value = value(vars, name);
+                    for _ in value.chars().count()..*width {
+                        out.push('0');
+                    }
+                    out.push_str(&value);
+                }
+            }
+        }
+        out
+    }
+
+    pub fn has_var(&self, name: &str) -> bool {
+        self.parts.iter().any(|part| match part {
+            Part::Var { name: var, .. } => var == name,
+            Part::Literal(_) => false,
+        })
+    }
+
+    /// Recovers the `{frame}` number from a file name rendered by this
+    /// template with `vars`, ignoring `frame` and `timestamp` in `vars`.
+    /// Returns `None` if the name doesn't match or has no frame number.
+    pub fn frame_number(&self, name: &str, vars: &HashMap<String, String>) -> Option<usize> {
+        match_parts(&self.parts, name, vars).and_then(|frame| frame)
+    }
+}
+
+fn match_parts(parts: &[Part], name: &str, vars: &HashMap<String, String>) -> Option<Option<usize>> {
+    let (part, rest) = match parts.split_first() {
+        Some(split) => split,
+        None if name.is_empty() => return Some(None),
+        None => return None,
+    };
+    match part {
+        Part::Literal(s) if name.starts_with(s.as_str()) => {
+            match_parts(rest, &name[s.len()..], vars)
+        }
+        Part::Literal(_) => None,
+        Part::Var { name: var, .. } if var == "frame" => {
+            let digits = name.bytes().take_while(u8::is_ascii_digit).count();
+            // Try the longest run first so `{frame}{seed}` style
+            // templates still resolve when seeds are numeric
+            (1..=digits).rev().find_map(|len| {
+                let frame = name[..len].parse().ok()?;
+                match_parts(rest, &name[len..], vars).map(|_| Some(frame))
+            })
+        }
+        Part::Var { name: var, .. } if var == "timestamp" => {
+            let stamp = name.get(..TIMESTAMP_LEN)?;
+            let is_stamp = stamp.bytes().enumerate().all(|(i, b)| match i {
+                8 => b == b'-',
+                _ => b.is_ascii_digit(),
+            });
+            if is_stamp {
+                match_parts(rest, &name[TIMESTAMP_LEN..], vars)
+            } else {
+                None
+            }
+        }
+        Part::Var { name: var, width } => {
+            let value = value(vars, var);
+            let pad = width.saturating_sub(value.chars().count());
+            let zeros = name.bytes().take(pad).take_while(|&b| b == b'0').count();
+            if zeros == pad && name[pad..].starts_with(value.as_str()) {
+                match_parts(rest, &name[pad + value.len()..], vars)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// The value of `name` as it appears in a file name.
+fn value(vars: &HashMap<String, String>, name: &str) -> String {
+    sanitize(vars.get(name).map(|s| s.as_str()).unwrap_or(""))
+}
+
+/// Replaces what would let `value` leave its directory or be refused by the file system.
+pub(crate) fn sanitize(value: &str) -> String {
+    if !value.is_empty() && value.chars().all(|c| c == '.') {
+        return "_".repeat(value.len());
+    }
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '<' | '>' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+impl Default for FilenameTemplate {
+    fn default() -> Self {
+        FilenameTemplate::parse(DEFAULT_TEMPLATE).unwrap()
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid filename template {:?}: {}", self.template, self.reason)
+    }
+}
+
+impl Error for TemplateError {}
+
+const TIMESTAMP_LEN: usize = 15;
+
+/// Formats `time` in UTC as `YYYYMMDD-HHMMSS`.
+pub(crate) fn timestamp(time: SystemTime) -> String {
+    let [year, month, day, hour, min, sec] = utc(time);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year, month, day, hour, min, sec
+    )
+}
+
+/// Formats `time` in UTC as ISO 8601, e.g. `2019-11-28T09:30:00Z`.
+pub(crate) fn iso8601(time: SystemTime) -> String {
+    let [year, month, day, hour, min, sec] = utc(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, hour, min, sec
+    )
+}
+
+/// Year, month, day, hour, minute and second of `time` in UTC.
+pub(crate) fn utc(time: SystemTime) -> [i64; 6] {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = ((secs / 86400) as i64, (secs % 86400) as i64);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    [year, month, day, rem / 3600, rem % 3600 / 60, rem % 60]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn values_stay_in_the_output_directory() {
+        let template = FilenameTemplate::parse("{name}_{frame}.png").unwrap();
+        let render = |value| template.render(&vars(&[("name", value), ("frame", "3")]));
+        assert_eq!(render("../../etc/passwd"), ".._.._etc_passwd_3.png");
+        assert_eq!(render("C:\\temp"), "C__temp_3.png");
+        assert_eq!(render("a.b"), "a.b_3.png");
+        let template = FilenameTemplate::parse("{name}/{frame}.png").unwrap();
+        let vars = vars(&[("name", ".."), ("frame", "3")]);
+        assert_eq!(template.render(&vars), "__/3.png");
+    }
+
+    #[test]
+    fn directory_names_stay_in_place() {
+        assert_eq!(sanitize("projector"), "projector");
+        assert_eq!(sanitize("../control"), ".._control");
+        assert_eq!(sanitize(".."), "__");
+        assert_eq!(sanitize("a\tb:c"), "a_b_c");
+    }
+
+    #[test]
+    fn frame_numbers_are_found_behind_replaced_values() {
+        let template = FilenameTemplate::parse("{seed}_{frame:03}.png").unwrap();
+        let vars = vars(&[("seed", "a/b")]);
+        assert_eq!(template.frame_number("a_b_012.png", &vars), Some(12));
+        assert_eq!(template.frame_number("a/b_012.png", &vars), None);
+    }
+}
@@ -0,0 +1,268 @@
+use super::format::SampleFormat;
+use super::{ImageData, NUM_COLOURS};
+use std::time::Instant;
+
+/// One sub-viewport of a poster started with `Shots::take_poster`.
+///
+/// The poster is the window scaled up `cols` times horizontally and
+/// `rows` times vertically. Tiles are rendered row by row from the top left.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub col: usize,
+    pub row: usize,
+    pub cols: usize,
+    pub rows: usize,
+}
+
+impl Tile {
+    /// Position of the tile in render order.
+    pub fn index(&self) -> usize {
+        self.row * self.cols + self.col
+    }
+
+    pub fn count(&self) -> usize {
+        self.cols * self.rows
+    }
+
+    pub fn is_last(&self) -> bool {
+        self.index() + 1 == self.count()
+    }
+
+    pub(crate) fn next(self) -> Option<Tile> {
+        if self.is_last() {
+            return None;
+        }
+        let index = self.index() + 1;
+        Some(Tile {
+            col: index % self.cols,
+            row: index / self.cols,
+            ..self
+        })
+    }
+
+    /// Scale to apply to the drawing on each axis.
+    pub fn scale(&self) -> (f32, f32) {
+        (self.cols as f32, self.rows as f32)
+    }
+
+    /// Translation to apply before scaling, in the coordinates of a
+    /// `w` by `h` window, so that this tile's part of the scene fills it.
+    ///
+    /// ```no_run
+    /// # use nannou::prelude::*;
+    /// # use screenshot::Shots;
+    /// # struct Model { screenshot: Shots }
+    /// # fn view(app: &App, model: &Model, frame: &Frame) {
+    /// let draw = app.draw();
+    /// let draw = match model.screenshot.tile() {
+    ///     Some(tile) => {
+    ///         let win = app.window_rect();
+    ///         let (sx, sy) = tile.scale();
+    ///         let (x, y) = tile.offset(win.w(), win.h());
+    ///         draw.scale_axes(vec3(sx, sy, 1.0)).x_y(x, y)
+    ///     }
+    ///     None => draw,
+    /// };
+    /// # }
+    /// ```
+    pub fn offset(&self, w: f32, h: f32) -> (f32, f32) {
+        let x = -w / 2.0 + (self.col as f32 + 0.5) * w / self.cols as f32;
+        let y = h / 2.0 - (self.row as f32 + 0.5) * h / self.rows as f32;
+        (-x, -y)
+    }
+}
+
+/// The image tiles are stitched into on the saving thread.
+pub(crate) struct Poster {
+    data: Vec<u8>,
+    dims: (usize, usize),
+    tile_dims: (usize, usize),
+    sample: SampleFormat,
+}
+
+impl Poster {
+    pub(crate) fn new(first: Tile, tile_dims: (usize, usize), sample: SampleFormat) -> Self {
+        let dims = (tile_dims.0 * first.cols, tile_dims.1 * first.rows);
+        let len = dims.0 * dims.1 * NUM_COLOURS * sample.bytes_per_sample();
+        Poster {
+            data: vec![0; len],
+            dims,
+            tile_dims,
+            sample,
+        }
+    }
+
+    /// Whether a tile of this size and precision can be copied in.
+    pub(crate) fn fits<B: ImageData>(&self, image: &B) -> bool {
+        image.dims() == self.tile_dims && image.sample_format() == self.sample
+    }
+
+    /// Copies the pixels of `tile` into place, waiting until
+    /// `deadline` for the GPU to finish with the image.
+    pub(crate) fn copy<B: ImageData>(
+        &mut self,
+        tile: Tile,
+        image: &B,
+        deadline: Instant,
+    ) -> Result<(), &'static str> {
+        let (w, h) = self.tile_dims;
+        let pixel = NUM_COLOURS * self.sample.bytes_per_sample();
+        let row_len = w * pixel;
+        let stride = self.dims.0 * pixel;
+        let data = &mut self.data;
+        image
+            .read_by(deadline, |tile_data| {
+                for (y, src) in tile_data.chunks_exact(row_len).take(h).enumerate() {
+                    let start = (tile.row * h + y) * stride + tile.col * row_len;
+                    data[start..start + row_len].copy_from_slice(src);
+                }
+            })
+            .ok_or("the GPU did not release it in time")
+    }
+}
+
+impl ImageData for Poster {
+    fn dims(&self) -> (usize, usize) {
+        self.dims
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        self.sample
+    }
+
+    fn read<R, F: FnOnce(&[u8]) -> R>(&self, f: F) -> Option<R> {
+        Some(f(&self.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Pixels in memory, standing in for a GPU buffer.
+    struct Image {
+        dims: (usize, usize),
+        sample: SampleFormat,
+        data: Vec<u8>,
+        held: bool,
+    }
+
+    impl ImageData for Image {
+        fn dims(&self) -> (usize, usize) {
+            self.dims
+        }
+
+        fn sample_format(&self) -> SampleFormat {
+            self.sample
+        }
+
+        fn read<R, F: FnOnce(&[u8]) -> R>(&self, f: F) -> Option<R> {
+            if self.held {
+                None
+            } else {
+                Some(f(&self.data))
+            }
+        }
+    }
+
+    fn image(dims: (usize, usize), sample: SampleFormat, value: u8) -> Image {
+        let len = dims.0 * dims.1 * NUM_COLOURS * sample.bytes_per_sample();
+        Image {
+            dims,
+            sample,
+            data: vec![value; len],
+            held: false,
+        }
+    }
+
+    fn first(cols: usize, rows: usize) -> Tile {
+        Tile {
+            col: 0,
+            row: 0,
+            cols,
+            rows,
+        }
+    }
+
+    #[test]
+    fn tiles_go_row_by_row() {
+        let mut tiles = vec![first(3, 2)];
+        while let Some(tile) = tiles.last().unwrap().next() {
+            tiles.push(tile);
+        }
+        let places: Vec<_> = tiles.iter().map(|tile| (tile.col, tile.row)).collect();
+        assert_eq!(places, [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]);
+        for (i, tile) in tiles.iter().enumerate() {
+            assert_eq!(tile.index(), i);
+            assert_eq!(tile.count(), 6);
+            assert_eq!(tile.is_last(), i == 5);
+        }
+        assert!(first(1, 1).is_last());
+        assert_eq!(first(1, 1).next(), None);
+    }
+
+    #[test]
+    fn offsets_centre_each_tile() {
+        assert_eq!(first(1, 1).offset(100.0, 60.0), (0.0, 0.0));
+        let tile = first(2, 3);
+        assert_eq!(tile.scale(), (2.0, 3.0));
+        assert_eq!(tile.offset(100.0, 60.0), (25.0, -20.0));
+        let last = Tile {
+            col: 1,
+            row: 2,
+            ..tile
+        };
+        assert_eq!(last.offset(100.0, 60.0), (-25.0, 20.0));
+        let middle = Tile {
+            col: 0,
+            row: 1,
+            ..tile
+        };
+        assert_eq!(middle.offset(100.0, 60.0), (25.0, 0.0));
+    }
+
+    #[test]
+    fn tiles_are_stitched_in_place() {
+        // Odd tile sizes and more rows than columns, so a mixed up
+        // stride or row length shows at the edges.
+        let tile_dims = (3, 1);
+        let sample = SampleFormat::U16;
+        let mut tile = first(2, 3);
+        let mut poster = Poster::new(tile, tile_dims, sample);
+        assert_eq!(poster.dims(), (6, 3));
+        let deadline = Instant::now() + Duration::from_secs(1);
+        loop {
+            let image = image(tile_dims, sample, tile.index() as u8 + 1);
+            assert!(poster.fits(&image));
+            poster.copy(tile, &image, deadline).unwrap();
+            match tile.next() {
+                Some(next) => tile = next,
+                None => break,
+            }
+        }
+        let pixel = NUM_COLOURS * sample.bytes_per_sample();
+        let data = poster.read(|data| data.to_vec()).unwrap();
+        assert_eq!(data.len(), 6 * 3 * pixel);
+        for (i, bytes) in data.chunks_exact(pixel).enumerate() {
+            let (x, y) = (i % 6, i / 6);
+            let index = y * 2 + x / 3;
+            assert!(
+                bytes.iter().all(|&b| b == index as u8 + 1),
+                "pixel {:?}",
+                (x, y)
+            );
+        }
+    }
+
+    #[test]
+    fn copying_needs_a_matching_released_image() {
+        let mut poster = Poster::new(first(2, 2), (2, 2), SampleFormat::U8);
+        assert!(!poster.fits(&image((2, 3), SampleFormat::U8, 0)));
+        assert!(!poster.fits(&image((2, 2), SampleFormat::F32, 0)));
+        let mut held = image((2, 2), SampleFormat::U8, 9);
+        held.held = true;
+        assert!(poster.copy(first(2, 2), &held, Instant::now()).is_err());
+        assert!(poster.read(|data| data.iter().all(|&b| b == 0)).unwrap());
+    }
+}
@@ -0,0 +1,121 @@
+use std::time::{Duration, Instant};
+
+/// How often `Shots::start_timelapse` takes a shot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interval {
+    /// Every `n` captured frames.
+    Frames(usize),
+    /// Every time this much wall clock time has passed.
+    Every(Duration),
+}
+
+/// State of a running timelapse.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Timelapse {
+    interval: Interval,
+    /// Shots left before the timelapse stops, or `None` to run until stopped.
+    remaining: Option<usize>,
+    frames: usize,
+    next: Option<Instant>,
+}
+
+impl Timelapse {
+    pub(crate) fn new(interval: Interval, max: Option<usize>) -> Self {
+        Timelapse {
+            interval,
+            remaining: max,
+            frames: 0,
+            next: None,
+        }
+    }
+
+    /// Whether a shot is due this frame. The first frame always is.
+    pub(crate) fn due(&mut self, now: Instant) -> bool {
+        let due = match self.interval {
+            Interval::Frames(n) => {
+                let due = self.frames % n.max(1) == 0;
+                self.frames += 1;
+                due
+            }
+            Interval::Every(period) => match self.next {
+                Some(next) if now < next => false,
+                // Keep to the schedule unless a whole period was missed
+                Some(next) if now < next + period => {
+                    self.next = Some(next + period);
+                    true
+                }
+                _ => {
+                    self.next = Some(now + period);
+                    true
+                }
+            },
+        };
+        if due {
+            self.remaining = self.remaining.map(|n| n.saturating_sub(1));
+        }
+        due
+    }
+
+    /// True once the maximum number of shots has been taken.
+    pub(crate) fn is_done(&self) -> bool {
+        self.remaining == Some(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn frames_are_counted_from_the_first() {
+        let now = Instant::now();
+        let mut timelapse = Timelapse::new(Interval::Frames(3), None);
+        let due: Vec<_> = (0..7).map(|_| timelapse.due(now)).collect();
+        assert_eq!(due, [true, false, false, true, false, false, true]);
+        // Zero frames is taken as every frame
+        let mut timelapse = Timelapse::new(Interval::Frames(0), None);
+        assert!((0..3).all(|_| timelapse.due(now)));
+        assert!(!timelapse.is_done());
+    }
+
+    #[test]
+    fn periods_keep_to_the_schedule() {
+        let start = Instant::now();
+        let mut timelapse = Timelapse::new(Interval::Every(ms(100)), None);
+        assert!(timelapse.due(start + ms(5)));
+        assert!(!timelapse.due(start + ms(104)));
+        // A late frame does not push the following shots back
+        assert!(timelapse.due(start + ms(130)));
+        assert!(!timelapse.due(start + ms(204)));
+        assert!(timelapse.due(start + ms(205)));
+    }
+
+    #[test]
+    fn missed_periods_restart_the_schedule() {
+        let start = Instant::now();
+        let mut timelapse = Timelapse::new(Interval::Every(ms(100)), None);
+        assert!(timelapse.due(start));
+        // Paused for longer than a period: one shot, not a burst of catch ups
+        assert!(timelapse.due(start + ms(350)));
+        assert!(!timelapse.due(start + ms(400)));
+        assert!(!timelapse.due(start + ms(449)));
+        assert!(timelapse.due(start + ms(450)));
+    }
+
+    #[test]
+    fn stops_after_the_maximum() {
+        let now = Instant::now();
+        let mut timelapse = Timelapse::new(Interval::Frames(2), Some(2));
+        assert!(timelapse.due(now));
+        assert!(!timelapse.due(now));
+        assert!(!timelapse.is_done());
+        assert!(timelapse.due(now));
+        assert!(timelapse.is_done());
+        let unlimited = Timelapse::new(Interval::Frames(1), None);
+        assert!(!unlimited.is_done());
+    }
+}
@@ -0,0 +1,108 @@
+/// Takes a shot once a generated piece is finished, see `Shots::capture_when`
+/// and `Shots::mark_complete`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Trigger {
+    /// Frames to wait after firing, so the last drawing has reached the frame.
+    settle: usize,
+    /// Frames left until the shot, once fired.
+    countdown: Option<usize>,
+    /// Whether the condition held last frame, so it only fires when it turns true.
+    held: bool,
+}
+
+impl Trigger {
+    pub(crate) fn new(settle: usize) -> Self {
+        Trigger {
+            settle,
+            countdown: None,
+            held: false,
+        }
+    }
+
+    pub(crate) fn set_settle(&mut self, settle: usize) {
+        self.settle = settle;
+    }
+
+    /// Counts down to a shot.
+    pub(crate) fn fire(&mut self) {
+        self.countdown = Some(self.settle);
+    }
+
+    /// Fires when `done` turns true and calls off a countdown when it turns false again.
+    pub(crate) fn update(&mut self, done: bool) {
+        if done && !self.held {
+            self.fire();
+        } else if !done {
+            self.countdown = None;
+        }
+        self.held = done;
+    }
+
+    /// Whether the shot is due this frame, counting down one frame otherwise.
+    pub(crate) fn due(&mut self) -> bool {
+        match self.countdown {
+            Some(0) => {
+                self.countdown = None;
+                true
+            }
+            Some(n) => {
+                self.countdown = Some(n - 1);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `update` then `due` for each frame's condition, like a sketch
+    /// calling `Shots::capture_when` before `Shots::capture`.
+    fn run(trigger: &mut Trigger, done: &[bool]) -> Vec<bool> {
+        done.iter()
+            .map(|&done| {
+                trigger.update(done);
+                trigger.due()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fires_after_settling() {
+        let mut trigger = Trigger::new(2);
+        let due = run(&mut trigger, &[false, true, true, true, true]);
+        assert_eq!(due, [false, false, false, true, false]);
+        let mut trigger = Trigger::new(0);
+        assert_eq!(run(&mut trigger, &[true, true]), [true, false]);
+    }
+
+    #[test]
+    fn fires_again_only_once_the_condition_clears() {
+        let mut trigger = Trigger::new(0);
+        let due = run(&mut trigger, &[true, true, false, true]);
+        assert_eq!(due, [true, false, false, true]);
+    }
+
+    #[test]
+    fn clearing_calls_off_the_countdown() {
+        let mut trigger = Trigger::new(2);
+        let due = run(&mut trigger, &[true, true, false, false, false]);
+        assert_eq!(due, [false; 5]);
+    }
+
+    #[test]
+    fn marked_complete_by_hand() {
+        let mut trigger = Trigger::new(1);
+        trigger.fire();
+        assert!(!trigger.due());
+        assert!(trigger.due());
+        assert!(!trigger.due());
+        // The settle time in effect when firing is used
+        trigger.set_settle(0);
+        trigger.fire();
+        trigger.set_settle(5);
+        assert!(trigger.due());
+    }
+}
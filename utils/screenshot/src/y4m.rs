@@ -0,0 +1,299 @@
+use super::format;
+use super::sink::{Counter, FrameInfo, FrameSink};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// Chroma resolution of a `Y4mSink` stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chroma {
+    /// Chroma averaged over 2x2 blocks, what most encoders expect.
+    C420,
+    /// Full resolution chroma.
+    C444,
+}
+
+/// Writes frames as an uncompressed YUV4MPEG2 stream for piping into a
+/// video encoder, instead of an image file per frame.
+///
+/// RGB is converted to BT.709 limited range YUV on the saving thread.
+/// Alpha is dropped.
+///
+/// ```no_run
+/// # use screenshot::{ScreenshotError, Shots, Y4mSink};
+/// # fn pipe(screenshot: &Shots) -> Result<(), ScreenshotError> {
+/// // cargo run --release | ffmpeg -i - -c:v libx264 out.mp4
+/// screenshot.set_sink(Y4mSink::stdout().frame_rate(60, 1))?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Y4mSink {
+    target: Target,
+    chroma: Chroma,
+    frame_rate: (u32, u32),
+    stream: Option<Stream>,
+}
+
+enum Target {
+    /// A new file for every recording, named after its first frame.
+    Files,
+    /// One stream for the whole session.
+    Writer(Option<Box<dyn Write + Send>>),
+}
+
+struct Stream {
+    out: Counter<Box<dyn Write + Send>>,
+    dims: (usize, usize),
+}
+
+impl Y4mSink {
+    /// Writes the frames between two flushes, such as one
+    /// recording, to a `.y4m` file named after the first frame.
+    pub fn new() -> Self {
+        Y4mSink::with_target(Target::Files)
+    }
+
+    /// Streams every frame to standard output.
+    pub fn stdout() -> Self {
+        Y4mSink::writer(BufWriter::new(io::stdout()))
+    }
+
+    /// Streams every frame to `w`. All frames must have the same size.
+    pub fn writer<W: Write + Send + 'static>(w: W) -> Self {
+        Y4mSink::with_target(Target::Writer(Some(Box::new(w))))
+    }
+
+    fn with_target(target: Target) -> Self {
+        Y4mSink {
+            target,
+            chroma: Chroma::C420,
+            frame_rate: (30, 1),
+            stream: None,
+        }
+    }
+
+    pub fn chroma(mut self, chroma: Chroma) -> Self {
+        self.chroma = chroma;
+        self
+    }
+
+    /// Frame rate as a fraction, e.g. `frame_rate(30000, 1001)` for 29.97.
+    pub fn frame_rate(mut self, num: u32, den: u32) -> Self {
+        self.frame_rate = (num.max(1), den.max(1));
+        self
+    }
+
+    fn start(&mut self, info: &FrameInfo) -> io::Result<Stream> {
+        let out: Box<dyn Write + Send> = match &mut self.target {
+            Target::Files => Box::new(BufWriter::new(File::create(&info.path)?)),
+            Target::Writer(w) => w.take().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::Other, "Y4M stream was already closed")
+            })?,
+        };
+        let chroma = match self.chroma {
+            Chroma::C420 => "C420jpeg",
+            Chroma::C444 => "C444",
+        };
+        let mut out = Counter::new(out);
+        writeln!(
+            out,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 {}",
+            info.dims.0, info.dims.1, self.frame_rate.0, self.frame_rate.1, chroma
+        )?;
+        Ok(Stream {
+            out,
+            dims: info.dims,
+        })
+    }
+}
+
+impl Default for Y4mSink {
+    fn default() -> Self {
+        Y4mSink::new()
+    }
+}
+
+impl FrameSink for Y4mSink {
+    fn write(&mut self, info: &FrameInfo, data: &[u8]) -> io::Result<u64> {
+        if self.stream.is_none() {
+            self.stream = Some(self.start(info)?);
+        }
+        let chroma = self.chroma;
+        let stream = self.stream.as_mut().unwrap();
+        if stream.dims != info.dims {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "frame is {}x{} but the stream is {}x{}",
+                    info.dims.0, info.dims.1, stream.dims.0, stream.dims.1
+                ),
+            ));
+        }
+        let rgba = format::to_u8(data, info.sample);
+        let (y, u, v) = rgb_to_yuv(&rgba, info.dims, chroma);
+        stream.out.write_all(b"FRAME\n")?;
+        stream.out.write_all(&y)?;
+        stream.out.write_all(&u)?;
+        stream.out.write_all(&v)?;
+        Ok(stream.out.take())
+    }
+
+    fn extension(&self) -> &str {
+        "y4m"
+    }
+
+    fn flush(&mut self) -> io::Result<u64> {
+        match self.target {
+            Target::Files => match self.stream.take() {
+                Some(mut stream) => stream.out.flush().map(|()| 0),
+                None => Ok(0),
+            },
+            // Later frames continue the same stream
+            Target::Writer(_) => match &mut self.stream {
+                Some(stream) => stream.out.flush().map(|()| 0),
+                None => Ok(0),
+            },
+        }
+    }
+}
+
+/// Converts RGBA to BT.709 limited range Y, U and V planes.
+///
+/// With `Chroma::C420` each chroma sample averages a 2x2 block,
+/// and odd sizes round the chroma planes up.
+pub(crate) fn rgb_to_yuv(
+    rgba: &[u8],
+    dims: (usize, usize),
+    chroma: Chroma,
+) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    const KR: f32 = 0.2126;
+    const KB: f32 = 0.0722;
+    let (w, h) = dims;
+    let luma = |r: f32, g: f32, b: f32| KR * r + (1.0 - KR - KB) * g + KB * b;
+    let to_u8 = |v: f32| v.round().max(0.0).min(255.0) as u8;
+    let y = rgba
+        .chunks_exact(4)
+        .map(|p| {
+            let l = luma(p[0] as f32, p[1] as f32, p[2] as f32);
+            to_u8(16.0 + l * 219.0 / 255.0)
+        })
+        .collect();
+    let (step, cw, ch) = match chroma {
+        Chroma::C420 => (2, (w + 1) / 2, (h + 1) / 2),
+        Chroma::C444 => (1, w, h),
+    };
+    let mut u = Vec::with_capacity(cw * ch);
+    let mut v = Vec::with_capacity(cw * ch);
+    for cy in 0..ch {
+        for cx in 0..cw {
+            let mut sum = [0.0; 3];
+            let mut n = 0.0;
+            for py in cy * step..((cy + 1) * step).min(h) {
+                for px in cx * step..((cx + 1) * step).min(w) {
+                    let i = (py * w + px) * 4;
+                    for c in 0..3 {
+                        sum[c] += rgba[i + c] as f32;
+                    }
+                    n += 1.0;
+                }
+            }
+            let (r, g, b) = (sum[0] / n, sum[1] / n, sum[2] / n);
+            let l = luma(r, g, b);
+            u.push(to_u8(128.0 + (b - l) / (2.0 * (1.0 - KB)) * 224.0 / 255.0));
+            v.push(to_u8(128.0 + (r - l) / (2.0 * (1.0 - KR)) * 224.0 / 255.0));
+        }
+    }
+    (y, u, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::SampleFormat;
+    use crate::metadata::Metadata;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps what the sink writes readable after the sink takes it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn info(dims: (usize, usize)) -> FrameInfo {
+        FrameInfo {
+            number: 1,
+            dims,
+            sample: SampleFormat::U8,
+            output_dir: PathBuf::new(),
+            path: PathBuf::from("frame.y4m"),
+            metadata: Metadata::new(),
+        }
+    }
+
+    fn yuv(rgb: [u8; 3]) -> [u8; 3] {
+        let (y, u, v) = rgb_to_yuv(&[rgb[0], rgb[1], rgb[2], 255], (1, 1), Chroma::C444);
+        [y[0], u[0], v[0]]
+    }
+
+    #[test]
+    fn bt709_limited_range() {
+        assert_eq!(yuv([0, 0, 0]), [16, 128, 128]);
+        assert_eq!(yuv([255, 255, 255]), [235, 128, 128]);
+        assert_eq!(yuv([255, 0, 0]), [63, 102, 240]);
+        assert_eq!(yuv([0, 255, 0]), [173, 42, 26]);
+        assert_eq!(yuv([0, 0, 255]), [32, 240, 118]);
+    }
+
+    #[test]
+    fn chroma_420_averages_blocks_and_rounds_up() {
+        // A white column next to black ones, 3x2
+        let mut rgba = vec![0; 3 * 2 * 4];
+        for row in rgba.chunks_exact_mut(12) {
+            row[..4].copy_from_slice(&[255, 255, 255, 255]);
+        }
+        let (y, u, v) = rgb_to_yuv(&rgba, (3, 2), Chroma::C420);
+        assert_eq!(y, [235, 16, 16, 235, 16, 16]);
+        assert_eq!((u.len(), v.len()), (2, 2));
+        assert_eq!(u, [128, 128]);
+    }
+
+    #[test]
+    fn stream_layout() {
+        let out = Shared::default();
+        let mut sink = Y4mSink::writer(out.clone()).frame_rate(30000, 1001);
+        let dims = (3, 3);
+        let mut bytes = 0;
+        for &value in &[0, 255] {
+            bytes += sink.write(&info(dims), &vec![value; 3 * 3 * 4]).unwrap();
+        }
+        bytes += sink.flush().unwrap();
+        let written = out.0.lock().unwrap().clone();
+        assert_eq!(bytes, written.len() as u64);
+        let header = b"YUV4MPEG2 W3 H3 F30000:1001 Ip A1:1 C420jpeg\n";
+        assert_eq!(&written[..header.len()], &header[..]);
+        // Y at full size, then U and V rounded up to 2x2
+        let frame = 6 + 9 + 4 + 4;
+        let frames = &written[header.len()..];
+        assert_eq!(frames.len(), 2 * frame);
+        for (frame, y) in frames.chunks_exact(frame).zip(&[16, 235]) {
+            assert_eq!(&frame[..6], b"FRAME\n");
+            assert!(frame[6..15].iter().all(|v| v == y));
+            assert!(frame[15..].iter().all(|&v| v == 128));
+        }
+    }
+
+    #[test]
+    fn stream_keeps_its_size() {
+        let mut sink = Y4mSink::writer(Shared::default()).chroma(Chroma::C444);
+        sink.write(&info((2, 2)), &[0; 16]).unwrap();
+        assert!(sink.write(&info((2, 1)), &[0; 8]).is_err());
+    }
+}